@@ -6,13 +6,40 @@ fn main() {
     // Set the environment variable so prost/tonic uses this binary
     std::env::set_var("PROTOC", protoc_path);
 
+    // Well-known types (google/protobuf/*.proto) ship alongside the vendored protoc
+    let protoc_include = protoc_bin_vendored::include_path()
+        .expect("Failed to locate vendored protoc include path");
+
+    let descriptor_path = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap())
+        .join("capture_descriptor.bin");
+
     // Optional: use a prost config if you want customization
     tonic_build::configure()
         .build_client(true)
         .build_server(true)
-        .compile(&["proto/capture.proto"], &["proto"])
+        // Descriptors and well-known types that pbjson can generate JSON mappings for
+        .file_descriptor_set_path(&descriptor_path)
+        .compile_well_known_types(true)
+        .extern_path(".google.protobuf", "::pbjson_types")
+        // Keep the rare, large snapshot payloads off the inline size of every message
+        .boxed(".capture.v1.Event.payload.system_info")
+        .boxed(".capture.v1.CommandAck.result.snapshot")
+        .boxed(".capture.v1.SystemInfoUpdate.kind.snapshot")
+        .boxed(".capture.v1.StreamMessage.kind.state")
+        .compile(
+            &["proto/capture/v1/capture.proto"],
+            &["proto".into(), protoc_include],
+        )
         .expect("Failed to compile .proto files");
 
-    println!("cargo:rerun-if-changed=proto/capture.proto");
+    // Serde implementations following the protobuf JSON mapping
+    let descriptor_set =
+        std::fs::read(&descriptor_path).expect("Failed to read the proto descriptor set");
+    pbjson_build::Builder::new()
+        .register_descriptors(&descriptor_set)
+        .expect("Failed to register proto descriptors")
+        .build(&[".capture"])
+        .expect("Failed to generate proto JSON mappings");
+
     println!("cargo:rerun-if-changed=proto");
 }
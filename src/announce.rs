@@ -0,0 +1,45 @@
+//! Tells the launching process where the agent can be reached, since ports
+//! may be assigned by the OS.
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+/// Prefix of the stdout line carrying the endpoints.
+const STDOUT_PREFIX: &str = "MOSS_AGENT_LISTEN";
+
+/// Bound addresses of every listener, omitting those that are off.
+#[derive(Debug, Default, Serialize)]
+pub struct Endpoints {
+    pub pid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unix_socket: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http: Option<SocketAddr>,
+    /// SHA-256 fingerprint of the gRPC server certificate, when TLS is on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_fingerprint: Option<String>,
+}
+
+impl Endpoints {
+    /// Prints the endpoints as one JSON line on stdout and, when `port_file`
+    /// is given, writes them there too. The file is replaced atomically so a
+    /// process polling for it never reads a partial write.
+    pub fn announce(&self, port_file: Option<&Path>) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        println!("{} {}", STDOUT_PREFIX, json);
+
+        if let Some(path) = port_file {
+            let mut temp = path.as_os_str().to_owned();
+            temp.push(".tmp");
+            fs::write(&temp, format!("{}\n", json))
+                .and_then(|_| fs::rename(&temp, path))
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
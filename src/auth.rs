@@ -0,0 +1,207 @@
+//! Shared-secret authentication for gRPC and HTTP gateway calls. Each launch
+//! generates a read-only and a control token, handed to the launching process
+//! through a private file or stdout. Clients send one as
+//! `authorization: Bearer <token>`.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::private_file::write_private;
+
+/// Bytes of randomness in each token.
+const TOKEN_BYTES: usize = 32;
+
+/// Prefix of the stdout line carrying the tokens when no file is configured.
+const STDOUT_PREFIX: &str = "MOSS_AGENT_AUTH";
+
+/// What a caller may do. Control includes everything read-only allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Status, configuration, system information and event streams
+    Read,
+    /// Also starting and stopping capture, changing configuration and
+    /// inserting markers
+    Control,
+}
+
+/// The tokens of this launch.
+pub struct Tokens {
+    read: String,
+    control: String,
+}
+
+impl Tokens {
+    pub fn generate() -> Result<Self, String> {
+        Ok(Tokens {
+            read: random_token()?,
+            control: random_token()?,
+        })
+    }
+
+    /// Hands the tokens to the launching process: written as JSON to `file`,
+    /// readable only by the current user, or printed on one stdout line.
+    pub fn publish(&self, file: Option<&Path>) -> Result<(), String> {
+        let json = serde_json::json!({
+            "read": self.read,
+            "control": self.control,
+        });
+        match file {
+            Some(path) => {
+                write_private(path, format!("{}\n", json).as_bytes())?;
+                println!("[INFO] Auth tokens written to {}", path.display());
+            }
+            None => println!("{} {}", STDOUT_PREFIX, json),
+        }
+        Ok(())
+    }
+
+    fn scope_of(&self, token: &str) -> Option<Scope> {
+        if constant_time_eq(token, &self.control) {
+            Some(Scope::Control)
+        } else if constant_time_eq(token, &self.read) {
+            Some(Scope::Read)
+        } else {
+            None
+        }
+    }
+}
+
+/// Rejects calls without a valid token and records the caller's scope as a
+/// request extension. Without tokens every call gets `Scope::Control`.
+#[derive(Clone)]
+pub struct TokenAuth {
+    tokens: Option<Arc<Tokens>>,
+}
+
+impl TokenAuth {
+    pub fn new(tokens: Option<Tokens>) -> Self {
+        TokenAuth {
+            tokens: tokens.map(Arc::new),
+        }
+    }
+
+    /// Scope granted to a caller presenting `token`.
+    pub fn authorize(&self, token: Option<&str>) -> Result<Scope, &'static str> {
+        match &self.tokens {
+            Some(tokens) => {
+                let token = token.ok_or("Missing bearer token")?;
+                tokens.scope_of(token.trim()).ok_or("Invalid token")
+            }
+            None => Ok(Scope::Control),
+        }
+    }
+}
+
+impl Interceptor for TokenAuth {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(bearer_token);
+        let scope = self.authorize(token).map_err(Status::unauthenticated)?;
+        request.extensions_mut().insert(scope);
+        Ok(request)
+    }
+}
+
+/// Extracts the token of an `authorization` header value.
+pub fn bearer_token(authorization: &str) -> Option<&str> {
+    authorization.strip_prefix("Bearer ")
+}
+
+/// Scope granted to `request`. Requests nobody authorized, which should not
+/// happen, only get `Scope::Read`.
+pub fn scope<T>(request: &Request<T>) -> Scope {
+    request
+        .extensions()
+        .get::<Scope>()
+        .copied()
+        .unwrap_or(Scope::Read)
+}
+
+/// Fails unless `request` has the control scope.
+pub fn require_control<T>(request: &Request<T>) -> Result<(), String> {
+    match scope(request) {
+        Scope::Control => Ok(()),
+        Scope::Read => Err("This call requires the control token".to_string()),
+    }
+}
+
+fn random_token() -> Result<String, String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("Failed to generate token: {}", e))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Compares without returning early, so timing does not reveal how much of
+/// a guessed token matched.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    fn auth() -> TokenAuth {
+        TokenAuth::new(Some(Tokens {
+            read: "read-token".to_string(),
+            control: "control-token".to_string(),
+        }))
+    }
+
+    fn call(auth: &mut TokenAuth, authorization: Option<&str>) -> Result<Scope, Code> {
+        let mut request = Request::new(());
+        if let Some(value) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", value.parse().unwrap());
+        }
+        auth.call(request)
+            .map(|request| scope(&request))
+            .map_err(|status| status.code())
+    }
+
+    #[test]
+    fn tokens_grant_their_scope() {
+        let mut auth = auth();
+        assert_eq!(
+            call(&mut auth, Some("Bearer read-token")).unwrap(),
+            Scope::Read
+        );
+        assert_eq!(
+            call(&mut auth, Some("Bearer control-token")).unwrap(),
+            Scope::Control
+        );
+    }
+
+    #[test]
+    fn rejects_missing_and_invalid_tokens() {
+        let mut auth = auth();
+        for authorization in [None, Some("Bearer wrong"), Some("control-token")] {
+            assert_eq!(call(&mut auth, authorization), Err(Code::Unauthenticated));
+        }
+    }
+
+    #[test]
+    fn without_tokens_everyone_has_control() {
+        assert_eq!(TokenAuth::new(None).authorize(None), Ok(Scope::Control));
+    }
+
+    #[test]
+    fn unauthorized_request_is_read_only() {
+        let request = Request::new(());
+        assert_eq!(scope(&request), Scope::Read);
+        assert!(require_control(&request).is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use crate::capture::BatchOptions;
+
+const DEFAULT_MAX_EVENTS: u32 = 100;
+const DEFAULT_MAX_DELAY_MS: u32 = 16;
+const MAX_BATCH_EVENTS: u32 = 1_000;
+const MAX_BATCH_DELAY_MS: u32 = 1_000;
+
+/// When a subscriber's pending batch of events is flushed.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchLimits {
+    pub max_events: usize,
+    pub max_delay: Duration,
+}
+
+impl BatchLimits {
+    /// Validates a subscriber's `BatchOptions`. Returns `None` if batching
+    /// was not requested.
+    pub fn new(options: Option<BatchOptions>) -> Result<Option<Self>, String> {
+        let Some(options) = options else {
+            return Ok(None);
+        };
+
+        if options.max_events > MAX_BATCH_EVENTS {
+            return Err(format!(
+                "batch.max_events must be at most {}, got {}",
+                MAX_BATCH_EVENTS, options.max_events
+            ));
+        }
+        if options.max_delay_ms > MAX_BATCH_DELAY_MS {
+            return Err(format!(
+                "batch.max_delay_ms must be at most {}, got {}",
+                MAX_BATCH_DELAY_MS, options.max_delay_ms
+            ));
+        }
+
+        let max_events = match options.max_events {
+            0 => DEFAULT_MAX_EVENTS,
+            n => n,
+        };
+        let max_delay_ms = match options.max_delay_ms {
+            0 => DEFAULT_MAX_DELAY_MS,
+            ms => ms,
+        };
+        Ok(Some(BatchLimits {
+            max_events: max_events as usize,
+            max_delay: Duration::from_millis(u64::from(max_delay_ms)),
+        }))
+    }
+}
@@ -0,0 +1,429 @@
+//! Command-line client for a running agent, for debugging without the
+//! Electron app.
+
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use tonic::metadata::MetadataValue;
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+use tonic::{Request, Status};
+
+use capture::capture_service_client::CaptureServiceClient;
+use capture::event::Payload;
+use capture::stream_message::Kind;
+use capture::{
+    Empty, Event, EventFilter, EventKind, GetSystemInfoRequest, InputAction, KeyCode, Marker,
+    MouseButton, StartRequest, StreamMessage, StreamRequest, StreamState,
+};
+
+pub mod capture {
+    pub mod v1 {
+        tonic::include_proto!("capture.v1");
+        include!(concat!(env!("OUT_DIR"), "/capture.v1.serde.rs"));
+    }
+
+    pub use v1::*;
+}
+
+#[derive(Parser)]
+#[command(
+    name = "moss-ctl",
+    version,
+    about = "Control and inspect a running agent"
+)]
+struct Cli {
+    /// Address of the agent's gRPC server, or unix:PATH for its Unix socket
+    #[arg(long, global = true, default_value = "http://127.0.0.1:50051")]
+    addr: String,
+    /// Connect over TLS, trusting the agent certificate in this PEM file,
+    /// e.g. its self-signed agent-cert.pem. An http:// --addr is switched to
+    /// https://
+    #[arg(long, global = true, value_name = "PATH")]
+    tls_cert: Option<PathBuf>,
+    /// Name the agent certificate must be valid for. Defaults to the --addr
+    /// host, or localhost when that is an IP address
+    #[arg(long, global = true, value_name = "NAME", requires = "tls_cert")]
+    tls_domain: Option<String>,
+    /// Present this PEM client certificate to an agent requiring one
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        requires_all = ["tls_cert", "tls_client_key"]
+    )]
+    tls_client_cert: Option<PathBuf>,
+    /// PEM private key of --tls-client-cert
+    #[arg(long, global = true, value_name = "PATH", requires = "tls_client_cert")]
+    tls_client_key: Option<PathBuf>,
+    /// Token of an agent started with --auth
+    #[arg(long, global = true, env = "MOSS_AGENT_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// Print responses and events as JSON, one object per line
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start a capture session
+    Start {
+        /// Label for the recording, e.g. a match or ticket id
+        #[arg(long, default_value = "")]
+        label: String,
+        /// Session metadata entry as KEY=VALUE; can be repeated
+        #[arg(long = "meta", value_parser = parse_key_value)]
+        metadata: Vec<(String, String)>,
+    },
+    /// Stop the capture session and print its summary
+    Stop,
+    /// Show capture state, counters and health
+    Status,
+    /// Show the agent's system information
+    SystemInfo {
+        /// Collect fresh information instead of using the cached snapshot
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// Show the agent version and supported features
+    Capabilities,
+    /// List connected event stream subscribers
+    Subscribers,
+    /// Insert a marker event
+    Marker {
+        name: String,
+        /// Marker metadata entry as KEY=VALUE; can be repeated
+        #[arg(long = "meta", value_parser = parse_key_value)]
+        metadata: Vec<(String, String)>,
+    },
+    /// Print events as they are published
+    Tail(TailArgs),
+}
+
+#[derive(Args)]
+struct TailArgs {
+    /// Event kinds to show, comma separated. All kinds if omitted
+    #[arg(long, value_enum, value_delimiter = ',')]
+    kinds: Vec<KindArg>,
+    /// Maximum MouseMove events per second. 0 means no extra limit
+    #[arg(long, default_value_t = 0)]
+    mouse_move_max_rate: u32,
+    /// Hide key codes and text, keeping only press/release timing
+    #[arg(long)]
+    redact_keys: bool,
+    /// Replay buffered events after this sequence number first
+    #[arg(long, conflicts_with = "initial_state")]
+    resume_after: Option<u64>,
+    /// Print the agent's current state before any events
+    #[arg(long)]
+    initial_state: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum KindArg {
+    Key,
+    Button,
+    MouseMove,
+    Wheel,
+    SystemInfo,
+    SystemInfoChange,
+    Marker,
+}
+
+impl From<KindArg> for EventKind {
+    fn from(kind: KindArg) -> Self {
+        match kind {
+            KindArg::Key => EventKind::Key,
+            KindArg::Button => EventKind::Button,
+            KindArg::MouseMove => EventKind::MouseMove,
+            KindArg::Wheel => EventKind::Wheel,
+            KindArg::SystemInfo => EventKind::SystemInfo,
+            KindArg::SystemInfoChange => EventKind::SystemInfoChange,
+            KindArg::Marker => EventKind::Marker,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let mut client = CaptureServiceClient::with_interceptor(
+        connect(&cli).await?,
+        BearerToken(cli.token.clone()),
+    );
+
+    match cli.command {
+        Command::Start { label, metadata } => {
+            let request = StartRequest {
+                label,
+                metadata: metadata.into_iter().collect(),
+            };
+            print(cli.json, &client.start(request).await?.into_inner())?;
+        }
+        Command::Stop => print(cli.json, &client.stop(Empty {}).await?.into_inner())?,
+        Command::Status => print(cli.json, &client.get_status(Empty {}).await?.into_inner())?,
+        Command::SystemInfo { refresh } => {
+            let request = GetSystemInfoRequest { refresh };
+            print(
+                cli.json,
+                &client.get_system_info(request).await?.into_inner(),
+            )?;
+        }
+        Command::Capabilities => print(
+            cli.json,
+            &client.get_capabilities(Empty {}).await?.into_inner(),
+        )?,
+        Command::Subscribers => print(
+            cli.json,
+            &client.list_subscribers(Empty {}).await?.into_inner(),
+        )?,
+        Command::Marker { name, metadata } => {
+            let marker = Marker {
+                name,
+                metadata: metadata.into_iter().collect(),
+            };
+            print(cli.json, &client.insert_marker(marker).await?.into_inner())?;
+        }
+        Command::Tail(args) => {
+            let request = StreamRequest {
+                filter: Some(EventFilter {
+                    kinds: args
+                        .kinds
+                        .into_iter()
+                        .map(|kind| EventKind::from(kind) as i32)
+                        .collect(),
+                    mouse_move_max_rate: args.mouse_move_max_rate,
+                    redact_keys: args.redact_keys,
+                }),
+                resume_after_sequence: args.resume_after,
+                batch: None,
+                initial_state: args.initial_state,
+            };
+            let mut stream = client.stream_events(request).await?.into_inner();
+            while let Some(message) = stream.message().await? {
+                if cli.json {
+                    println!("{}", serde_json::to_string(&message)?);
+                } else {
+                    print_stream_message(&message);
+                }
+            }
+            eprintln!("Event stream ended");
+        }
+    }
+
+    Ok(())
+}
+
+async fn connect(cli: &Cli) -> Result<Channel, Box<dyn std::error::Error>> {
+    #[cfg(unix)]
+    if let Some(path) = cli.addr.strip_prefix("unix:") {
+        if cli.tls_cert.is_some() {
+            return Err("--tls-cert does not apply to a Unix socket, which is plaintext".into());
+        }
+        let path = PathBuf::from(path);
+        // The URI is required but unused; every connection goes to the socket
+        let channel = tonic::transport::Endpoint::from_static("http://localhost")
+            .connect_with_connector(tower::service_fn(move |_| {
+                tokio::net::UnixStream::connect(path.clone())
+            }))
+            .await?;
+        return Ok(channel);
+    }
+
+    let mut addr = cli.addr.clone();
+    if cli.tls_cert.is_some() {
+        // tonic only uses the TLS config for https URIs
+        if let Some(rest) = addr.strip_prefix("http://") {
+            addr = format!("https://{}", rest);
+        }
+    }
+    let mut endpoint = Channel::from_shared(addr)?;
+    if let Some(path) = &cli.tls_cert {
+        let pem = std::fs::read(path)?;
+        let mut tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(pem));
+        // The self-signed certificate is verified as localhost when the agent
+        // is addressed by IP; a host name is checked as is
+        let is_ip = endpoint
+            .uri()
+            .host()
+            .is_some_and(|host| host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok());
+        match &cli.tls_domain {
+            Some(domain) => tls = tls.domain_name(domain),
+            None if is_ip => tls = tls.domain_name("localhost"),
+            None => {}
+        }
+        if let (Some(cert), Some(key)) = (&cli.tls_client_cert, &cli.tls_client_key) {
+            tls = tls.identity(Identity::from_pem(
+                std::fs::read(cert)?,
+                std::fs::read(key)?,
+            ));
+        }
+        endpoint = endpoint.tls_config(tls)?;
+    }
+    Ok(endpoint.connect().await?)
+}
+
+/// Adds the `--token` to every call.
+struct BearerToken(Option<String>);
+
+impl Interceptor for BearerToken {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.0 {
+            let value = MetadataValue::try_from(format!("Bearer {}", token))
+                .map_err(|_| Status::invalid_argument("Token is not valid metadata"))?;
+            request.metadata_mut().insert("authorization", value);
+        }
+        Ok(request)
+    }
+}
+
+/// Prints a unary response as compact JSON, or indented JSON for reading.
+fn print<T: Serialize>(json: bool, message: &T) -> serde_json::Result<()> {
+    let text = if json {
+        serde_json::to_string(message)?
+    } else {
+        serde_json::to_string_pretty(message)?
+    };
+    println!("{}", text);
+    Ok(())
+}
+
+fn print_stream_message(message: &StreamMessage) {
+    match &message.kind {
+        Some(Kind::Event(event)) => println!("{}", format_event(event)),
+        Some(Kind::Batch(batch)) => {
+            for event in &batch.events {
+                println!("{}", format_event(event));
+            }
+        }
+        Some(Kind::EventsDropped(dropped)) => println!(
+            "-- dropped {} events ({} total) --",
+            dropped.count, dropped.total
+        ),
+        Some(Kind::State(state)) => println!("{}", format_state(state)),
+        None => {}
+    }
+}
+
+fn format_state(state: &StreamState) -> String {
+    let capture = if state.capturing {
+        format!("capturing in session {}", state.session_id)
+    } else {
+        "not capturing".to_string()
+    };
+    let keys: Vec<_> = state
+        .pressed_keys
+        .iter()
+        .map(|key| key_name(key.key, key.raw_code))
+        .collect();
+    let buttons: Vec<_> = state
+        .pressed_buttons
+        .iter()
+        .map(|button| button_name(button.button, button.raw_code))
+        .collect();
+    format!(
+        "-- state at sequence {}: {}; keys held [{}]; buttons held [{}] --",
+        state.sequence,
+        capture,
+        keys.join(", "),
+        buttons.join(", ")
+    )
+}
+
+/// One line per event: sequence, local time and a short description.
+fn format_event(event: &Event) -> String {
+    let time = event
+        .timestamp
+        .as_ref()
+        .and_then(|ts| chrono::DateTime::from_timestamp(ts.seconds, ts.nanos as u32))
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%H:%M:%S%.3f")
+                .to_string()
+        })
+        .unwrap_or_default();
+
+    let description = match &event.payload {
+        Some(Payload::Key(key)) => {
+            let mut text = format!(
+                "key {} {}",
+                action_name(key.action),
+                key_name(key.key, key.raw_code)
+            );
+            if !key.text.is_empty() {
+                text.push_str(&format!(" {:?}", key.text));
+            }
+            text
+        }
+        Some(Payload::Button(button)) => format!(
+            "button {} {}",
+            action_name(button.action),
+            button_name(button.button, button.raw_code)
+        ),
+        Some(Payload::MouseMove(mouse)) => format!("mouse move ({}, {})", mouse.x, mouse.y),
+        Some(Payload::Wheel(wheel)) => {
+            format!("wheel dx={} dy={}", wheel.delta_x, wheel.delta_y)
+        }
+        Some(Payload::SystemInfo(_)) => "system info snapshot".to_string(),
+        Some(Payload::SystemInfoChange(change)) => {
+            let changes: Vec<_> = change
+                .changes
+                .iter()
+                .map(|c| format!("{}: {:?} -> {:?}", c.path, c.old_value, c.new_value))
+                .collect();
+            format!("system info changed: {}", changes.join("; "))
+        }
+        Some(Payload::Marker(marker)) => {
+            let mut metadata: Vec<_> = marker
+                .metadata
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            metadata.sort();
+            format!("marker {:?} {}", marker.name, metadata.join(" "))
+        }
+        None => "unknown event".to_string(),
+    };
+
+    format!("{:>8} {} {}", event.sequence, time, description)
+}
+
+fn action_name(action: i32) -> &'static str {
+    match InputAction::try_from(action) {
+        Ok(InputAction::Press) => "press",
+        Ok(InputAction::Release) => "release",
+        _ => "?",
+    }
+}
+
+fn key_name(key: i32, raw_code: u32) -> String {
+    match KeyCode::try_from(key) {
+        Ok(KeyCode::Unknown) => format!("unknown({})", raw_code),
+        Ok(KeyCode::Unspecified) => "redacted".to_string(),
+        Ok(code) => short_name(code.as_str_name(), "KEY_CODE_"),
+        Err(_) => format!("{}", key),
+    }
+}
+
+fn button_name(button: i32, raw_code: u32) -> String {
+    match MouseButton::try_from(button) {
+        Ok(MouseButton::Unknown) => format!("unknown({})", raw_code),
+        Ok(button) => short_name(button.as_str_name(), "MOUSE_BUTTON_"),
+        Err(_) => format!("{}", button),
+    }
+}
+
+fn short_name(name: &str, prefix: &str) -> String {
+    name.strip_prefix(prefix).unwrap_or(name).to_lowercase()
+}
+
+fn parse_key_value(entry: &str) -> Result<(String, String), String> {
+    entry
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {:?}", entry))
+}
@@ -0,0 +1,359 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, TryRecvError},
+};
+
+use crate::capture::event::Payload;
+use crate::capture::{
+    ButtonEvent, Event, EventKind, InputAction, KeyEvent, StreamState, SystemInfoFieldChange,
+};
+use crate::events;
+
+/// Bounds on how many recent events are kept for resuming streams.
+pub struct ReplayLimits {
+    pub max_events: usize,
+    pub max_age: Duration,
+}
+
+/// What was published during a capture session.
+pub struct SessionTally {
+    id: String,
+    pub published: HashMap<EventKind, u64>,
+    pub system_changes: Vec<SystemInfoFieldChange>,
+}
+
+/// Fans events out to all subscribers, stamping each one with a sequence
+/// number and timestamps on the way.
+///
+/// Captured input and the rare system and marker events travel on separate
+/// channels, so a flood of input never pushes the others out of a lagging
+/// subscriber's buffer. Stamping and sending happen under the same lock,
+/// which lets `EventReceiver` merge the two back into sequence order.
+pub struct EventBroadcaster {
+    input: broadcast::Sender<Event>,
+    system: broadcast::Sender<Event>,
+    started: Instant,
+    replay_limits: ReplayLimits,
+    state: Mutex<State>,
+}
+
+struct State {
+    last_sequence: u64,
+    /// Most recent events, oldest first
+    replay: VecDeque<Event>,
+    published: HashMap<EventKind, u64>,
+    session: Option<SessionTally>,
+    /// Published presses not yet released, keyed by (code, raw code)
+    pressed_keys: BTreeMap<(i32, u32), KeyEvent>,
+    pressed_buttons: BTreeMap<(i32, u32), ButtonEvent>,
+}
+
+impl EventBroadcaster {
+    pub fn new(input_capacity: usize, system_capacity: usize, replay_limits: ReplayLimits) -> Self {
+        let (input, _) = broadcast::channel(input_capacity);
+        let (system, _) = broadcast::channel(system_capacity);
+        EventBroadcaster {
+            input,
+            system,
+            started: Instant::now(),
+            replay_limits,
+            state: Mutex::new(State {
+                last_sequence: 0,
+                replay: VecDeque::new(),
+                published: HashMap::new(),
+                session: None,
+                pressed_keys: BTreeMap::new(),
+                pressed_buttons: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub fn last_sequence(&self) -> u64 {
+        self.state.lock().unwrap().last_sequence
+    }
+
+    /// Number of events published so far for each kind.
+    pub fn published_counts(&self) -> HashMap<EventKind, u64> {
+        self.state.lock().unwrap().published.clone()
+    }
+
+    /// Tags every event published from now on with `session_id` and starts
+    /// tallying them, replacing any previous session.
+    pub fn begin_session(&self, session_id: String) {
+        let mut state = self.state.lock().unwrap();
+        state.clear_pressed();
+        state.session = Some(SessionTally {
+            id: session_id,
+            published: HashMap::new(),
+            system_changes: Vec::new(),
+        });
+    }
+
+    /// Stops tagging events and returns what was published during the session.
+    pub fn end_session(&self) -> Option<SessionTally> {
+        let mut state = self.state.lock().unwrap();
+        // Releases are not captured after this, so presses would go stale
+        state.clear_pressed();
+        state.session.take()
+    }
+
+    pub fn subscribe(&self) -> EventReceiver {
+        EventReceiver {
+            input: self.input.subscribe(),
+            system: self.system.subscribe(),
+            next_input: None,
+            next_system: None,
+        }
+    }
+
+    /// Subscribes to live events and returns the state they start from. The
+    /// returned state has no system information; that is not tracked here.
+    pub fn subscribe_with_state(&self) -> (EventReceiver, StreamState) {
+        let state = self.state.lock().unwrap();
+        let stream_state = StreamState {
+            sequence: state.last_sequence,
+            capturing: state.session.is_some(),
+            session_id: state
+                .session
+                .as_ref()
+                .map(|session| session.id.clone())
+                .unwrap_or_default(),
+            system_info: None,
+            pressed_keys: state.pressed_keys.values().cloned().collect(),
+            pressed_buttons: state.pressed_buttons.values().cloned().collect(),
+        };
+        (self.subscribe(), stream_state)
+    }
+
+    /// Subscribes to live events and returns the buffered events with a
+    /// sequence number above `sequence`, which precede the first live event
+    /// without gaps or overlap.
+    pub fn subscribe_after(&self, sequence: u64) -> Result<(EventReceiver, Vec<Event>), String> {
+        let mut state = self.state.lock().unwrap();
+        self.evict_expired(&mut state);
+
+        if sequence > state.last_sequence {
+            return Err(format!(
+                "Sequence {} has not been issued yet (latest is {}); the agent may have restarted",
+                sequence, state.last_sequence
+            ));
+        }
+        let oldest = state
+            .replay
+            .front()
+            .map_or(state.last_sequence + 1, |event| event.sequence);
+        if sequence + 1 < oldest {
+            return Err(format!(
+                "Events after sequence {} have been evicted; oldest available is {}",
+                sequence, oldest
+            ));
+        }
+
+        let missed = state
+            .replay
+            .iter()
+            .filter(|event| event.sequence > sequence)
+            .cloned()
+            .collect();
+        Ok((self.subscribe(), missed))
+    }
+
+    /// Publishes an event that happened at `time` (e.g. the OS timestamp of an
+    /// input event) and returns its sequence number.
+    pub fn publish_at(&self, payload: Payload, time: SystemTime) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let kind = events::kind_of(&payload);
+        *state.published.entry(kind).or_default() += 1;
+
+        match &payload {
+            Payload::Key(key) => {
+                let id = (key.key, key.raw_code);
+                if key.action == InputAction::Press as i32 {
+                    state.pressed_keys.insert(id, key.clone());
+                } else {
+                    state.pressed_keys.remove(&id);
+                }
+            }
+            Payload::Button(button) => {
+                let id = (button.button, button.raw_code);
+                if button.action == InputAction::Press as i32 {
+                    state.pressed_buttons.insert(id, button.clone());
+                } else {
+                    state.pressed_buttons.remove(&id);
+                }
+            }
+            _ => {}
+        }
+
+        let mut session_id = String::new();
+        if let Some(session) = &mut state.session {
+            *session.published.entry(kind).or_default() += 1;
+            if let Payload::SystemInfoChange(change) = &payload {
+                session
+                    .system_changes
+                    .extend(change.changes.iter().cloned());
+            }
+            session_id = session.id.clone();
+        }
+
+        state.last_sequence += 1;
+        let event = Event {
+            sequence: state.last_sequence,
+            timestamp: Some(events::timestamp(time)),
+            monotonic_ns: self.started.elapsed().as_nanos() as u64,
+            session_id,
+            payload: Some(payload),
+        };
+
+        if state.replay.len() >= self.replay_limits.max_events {
+            state.replay.pop_front();
+        }
+        state.replay.push_back(event.clone());
+        self.evict_expired(&mut state);
+
+        let lane = if events::is_input(kind) {
+            &self.input
+        } else {
+            &self.system
+        };
+        // Sending only fails when nobody is subscribed, which is not an error
+        let _ = lane.send(event);
+        state.last_sequence
+    }
+
+    /// Publishes an event that happened just now and returns its sequence number.
+    pub fn publish(&self, payload: Payload) -> u64 {
+        self.publish_at(payload, SystemTime::now())
+    }
+
+    fn evict_expired(&self, state: &mut State) {
+        let max_age_ns = self.replay_limits.max_age.as_nanos() as u64;
+        let now_ns = self.started.elapsed().as_nanos() as u64;
+        while let Some(oldest) = state.replay.front() {
+            if now_ns.saturating_sub(oldest.monotonic_ns) <= max_age_ns {
+                break;
+            }
+            state.replay.pop_front();
+        }
+    }
+}
+
+impl State {
+    fn clear_pressed(&mut self) {
+        self.pressed_keys.clear();
+        self.pressed_buttons.clear();
+    }
+}
+
+/// A subscriber's end of both broadcast lanes, delivering their events in
+/// sequence order.
+pub struct EventReceiver {
+    input: broadcast::Receiver<Event>,
+    system: broadcast::Receiver<Event>,
+    /// Next event received from each lane, held until no lower sequence is pending
+    next_input: Option<Event>,
+    next_system: Option<Event>,
+}
+
+impl EventReceiver {
+    /// Receives the next event, failing like `broadcast::Receiver::recv` when
+    /// either lane lagged or the broadcaster has shut down. Cancel safe.
+    pub async fn recv(&mut self) -> Result<Event, RecvError> {
+        loop {
+            // An event is only ever taken after polling the other lane: anything
+            // published before it has been sent by then, so an empty lane means
+            // nothing earlier is outstanding
+            if self.next_input.is_none() {
+                self.next_input = poll_lane(&mut self.input)?;
+            }
+            if self.next_system.is_none() {
+                self.next_system = poll_lane(&mut self.system)?;
+            }
+
+            let take_input = match (&self.next_input, &self.next_system) {
+                (Some(input), Some(system)) => input.sequence < system.sequence,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => {
+                    tokio::select! {
+                        event = self.input.recv() => self.next_input = Some(event?),
+                        event = self.system.recv() => self.next_system = Some(event?),
+                    }
+                    continue;
+                }
+            };
+            let next = if take_input {
+                self.next_input.take()
+            } else {
+                self.next_system.take()
+            };
+            if let Some(event) = next {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+/// Takes an event from a lane without waiting. A closed lane reads as empty;
+/// the closure is reported once both lanes are waited on.
+fn poll_lane(rx: &mut broadcast::Receiver<Event>) -> Result<Option<Event>, RecvError> {
+    match rx.try_recv() {
+        Ok(event) => Ok(Some(event)),
+        Err(TryRecvError::Empty | TryRecvError::Closed) => Ok(None),
+        Err(TryRecvError::Lagged(count)) => Err(RecvError::Lagged(count)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{broadcaster, marker, mouse_move, publish_markers};
+
+    #[tokio::test]
+    async fn receiver_merges_lanes_in_sequence_order_after_lag() {
+        let broadcaster = broadcaster(4, 100);
+        let mut receiver = broadcaster.subscribe();
+
+        broadcaster.publish(mouse_move()); // 1
+        broadcaster.publish(marker()); // 2
+        for _ in 3..=10 {
+            broadcaster.publish(mouse_move());
+        }
+        broadcaster.publish(marker()); // 11
+        broadcaster.publish(mouse_move()); // 12
+
+        assert!(matches!(receiver.recv().await, Err(RecvError::Lagged(_))));
+        // The input lane only kept its last four events; both markers survive
+        let mut sequences = Vec::new();
+        for _ in 0..6 {
+            sequences.push(receiver.recv().await.unwrap().sequence);
+        }
+        assert_eq!(sequences, [2, 8, 9, 10, 11, 12]);
+    }
+
+    #[test]
+    fn subscribe_after_replays_missed_events() {
+        let broadcaster = broadcaster(16, 3);
+        publish_markers(&broadcaster, 5);
+
+        let (_, missed) = broadcaster.subscribe_after(2).unwrap();
+        let sequences: Vec<u64> = missed.iter().map(|event| event.sequence).collect();
+        assert_eq!(sequences, [3, 4, 5]);
+    }
+
+    #[test]
+    fn subscribe_after_rejects_evicted_sequence() {
+        let broadcaster = broadcaster(16, 3);
+        publish_markers(&broadcaster, 5);
+
+        // Only 3..=5 are buffered, so events 2 and up cannot all be replayed
+        let error = broadcaster.subscribe_after(1).err().unwrap();
+        assert!(error.contains("evicted"), "{}", error);
+        let error = broadcaster.subscribe_after(6).err().unwrap();
+        assert!(error.contains("not been issued"), "{}", error);
+    }
+}
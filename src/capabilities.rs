@@ -0,0 +1,53 @@
+use crate::capture as proto;
+use crate::capture::{Collector, EventKind};
+use crate::config::CaptureConfig;
+use crate::events;
+
+/// Proto packages served, newest first.
+pub const API_VERSIONS: [&str; 1] = ["capture.v1"];
+
+/// Bump whenever RPCs, messages or fields are added to the newest package.
+pub const SCHEMA_REVISION: u32 = 1;
+
+/// Optional protocol features clients can rely on when listed.
+const FEATURES: [&str; 7] = [
+    "event_filters",
+    "stream_resume",
+    "event_batching",
+    "initial_state",
+    "session_stream",
+    "markers",
+    "watch_system_info",
+];
+
+/// Describes this agent binary and its current collectors.
+pub fn capabilities(config: &CaptureConfig, listener_alive: bool) -> proto::Capabilities {
+    let input_enabled = events::ALL_KINDS
+        .into_iter()
+        .any(|kind| events::is_input(kind) && config.is_enabled(kind));
+    let system_info_enabled =
+        config.is_enabled(EventKind::SystemInfo) || config.is_enabled(EventKind::SystemInfoChange);
+
+    proto::Capabilities {
+        agent_version: env!("CARGO_PKG_VERSION").to_string(),
+        api_versions: API_VERSIONS.iter().map(|v| v.to_string()).collect(),
+        schema_revision: SCHEMA_REVISION,
+        event_kinds: events::ALL_KINDS.into_iter().map(|k| k as i32).collect(),
+        collectors: vec![
+            Collector {
+                name: "input".to_string(),
+                available: listener_alive,
+                enabled: input_enabled,
+            },
+            Collector {
+                name: "system_info".to_string(),
+                // Collection shells out to wmic, ipconfig and PowerShell
+                available: cfg!(windows),
+                enabled: system_info_enabled,
+            },
+        ],
+        input_backend: "rdev".to_string(),
+        platform: format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH),
+        features: FEATURES.iter().map(|f| f.to_string()).collect(),
+    }
+}
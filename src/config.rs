@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::capture as proto;
+use crate::capture::EventKind;
+use crate::events;
+
+const MAX_MOUSE_MOVE_INTERVAL_MS: u32 = 1_000;
+const MIN_SYSTEM_POLL_INTERVAL_MS: u32 = 1_000;
+const MAX_SYSTEM_POLL_INTERVAL_MS: u32 = 3_600_000;
+
+/// Capture settings shared by the input listener and the system monitor.
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    pub mouse_move_interval: Duration,
+    pub system_poll_interval: Duration,
+    pub enabled_kinds: HashSet<EventKind>,
+    pub suppress_key_repeats: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            mouse_move_interval: Duration::from_millis(50),
+            system_poll_interval: Duration::from_secs(5),
+            enabled_kinds: events::ALL_KINDS
+                .into_iter()
+                .filter(|&kind| kind != EventKind::Marker)
+                .collect(),
+            suppress_key_repeats: true,
+        }
+    }
+}
+
+impl CaptureConfig {
+    pub fn is_enabled(&self, kind: EventKind) -> bool {
+        self.enabled_kinds.contains(&kind)
+    }
+
+    /// Returns a copy of this config with `update` applied, or a description
+    /// of the first invalid field.
+    pub fn with_update(&self, update: &proto::UpdateConfigRequest) -> Result<Self, String> {
+        let mut config = self.clone();
+
+        if let Some(ms) = update.mouse_move_interval_ms {
+            if ms > MAX_MOUSE_MOVE_INTERVAL_MS {
+                return Err(format!(
+                    "mouse_move_interval_ms must be at most {}, got {}",
+                    MAX_MOUSE_MOVE_INTERVAL_MS, ms
+                ));
+            }
+            config.mouse_move_interval = Duration::from_millis(u64::from(ms));
+        }
+
+        if let Some(ms) = update.system_poll_interval_ms {
+            if !(MIN_SYSTEM_POLL_INTERVAL_MS..=MAX_SYSTEM_POLL_INTERVAL_MS).contains(&ms) {
+                return Err(format!(
+                    "system_poll_interval_ms must be between {} and {}, got {}",
+                    MIN_SYSTEM_POLL_INTERVAL_MS, MAX_SYSTEM_POLL_INTERVAL_MS, ms
+                ));
+            }
+            config.system_poll_interval = Duration::from_millis(u64::from(ms));
+        }
+
+        if let Some(list) = &update.enabled_kinds {
+            let kinds = events::parse_kinds(&list.kinds)?;
+            if kinds.contains(&EventKind::Marker) {
+                return Err(
+                    "EVENT_KIND_MARKER is not accepted in enabled_kinds; markers are \
+                     inserted by clients and always published"
+                        .to_string(),
+                );
+            }
+            config.enabled_kinds = kinds;
+        }
+
+        if let Some(suppress) = update.suppress_key_repeats {
+            config.suppress_key_repeats = suppress;
+        }
+
+        Ok(config)
+    }
+
+    pub fn to_proto(&self) -> proto::CaptureConfig {
+        proto::CaptureConfig {
+            mouse_move_interval_ms: self.mouse_move_interval.as_millis() as u32,
+            system_poll_interval_ms: self.system_poll_interval.as_millis() as u32,
+            enabled_kinds: events::ALL_KINDS
+                .into_iter()
+                .filter(|kind| self.is_enabled(*kind))
+                .map(|kind| kind as i32)
+                .collect(),
+            suppress_key_repeats: self.suppress_key_repeats,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{EventKindList, UpdateConfigRequest};
+
+    fn enabled_kinds(kinds: &[EventKind]) -> UpdateConfigRequest {
+        UpdateConfigRequest {
+            enabled_kinds: Some(EventKindList {
+                kinds: kinds.iter().map(|&kind| kind as i32).collect(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unset_fields_keep_their_value() {
+        let config = CaptureConfig::default()
+            .with_update(&UpdateConfigRequest {
+                suppress_key_repeats: Some(false),
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert!(!config.suppress_key_repeats);
+        assert_eq!(config.mouse_move_interval, Duration::from_millis(50));
+        assert_eq!(config.enabled_kinds, CaptureConfig::default().enabled_kinds);
+    }
+
+    #[test]
+    fn limits_mouse_move_interval() {
+        let update = |ms| UpdateConfigRequest {
+            mouse_move_interval_ms: Some(ms),
+            ..Default::default()
+        };
+        let config = CaptureConfig::default()
+            .with_update(&update(MAX_MOUSE_MOVE_INTERVAL_MS))
+            .unwrap();
+        assert_eq!(config.mouse_move_interval, Duration::from_secs(1));
+        assert!(CaptureConfig::default()
+            .with_update(&update(MAX_MOUSE_MOVE_INTERVAL_MS + 1))
+            .is_err());
+    }
+
+    #[test]
+    fn limits_system_poll_interval() {
+        let update = |ms| UpdateConfigRequest {
+            system_poll_interval_ms: Some(ms),
+            ..Default::default()
+        };
+        for ms in [MIN_SYSTEM_POLL_INTERVAL_MS, MAX_SYSTEM_POLL_INTERVAL_MS] {
+            assert!(CaptureConfig::default().with_update(&update(ms)).is_ok());
+        }
+        for ms in [
+            0,
+            MIN_SYSTEM_POLL_INTERVAL_MS - 1,
+            MAX_SYSTEM_POLL_INTERVAL_MS + 1,
+        ] {
+            assert!(CaptureConfig::default().with_update(&update(ms)).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_and_unspecified_kinds() {
+        assert!(CaptureConfig::default()
+            .with_update(&enabled_kinds(&[EventKind::Unspecified]))
+            .is_err());
+        let unknown = UpdateConfigRequest {
+            enabled_kinds: Some(EventKindList { kinds: vec![999] }),
+            ..Default::default()
+        };
+        assert!(CaptureConfig::default().with_update(&unknown).is_err());
+    }
+
+    #[test]
+    fn rejects_marker_kind() {
+        let error = CaptureConfig::default()
+            .with_update(&enabled_kinds(&[EventKind::Key, EventKind::Marker]))
+            .unwrap_err();
+        assert!(error.contains("not accepted"), "{}", error);
+    }
+
+    #[test]
+    fn empty_kind_list_disables_every_kind() {
+        let config = CaptureConfig::default()
+            .with_update(&enabled_kinds(&[]))
+            .unwrap();
+        assert!(events::ALL_KINDS
+            .into_iter()
+            .all(|kind| !config.is_enabled(kind)));
+    }
+}
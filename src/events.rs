@@ -0,0 +1,257 @@
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+use rdev::{Button, Event as RdevEvent, EventType, Key};
+
+use crate::capture::event::Payload;
+use crate::capture::{
+    ButtonEvent, EventKind, InputAction, KeyCode, KeyEvent, Marker, MouseButton, MouseMoveEvent,
+    WheelEvent,
+};
+
+const MAX_MARKER_NAME_LEN: usize = 256;
+const MAX_MARKER_METADATA_ENTRIES: usize = 32;
+const MAX_MARKER_METADATA_LEN: usize = 1024;
+
+/// Converts a raw rdev event into its typed `Event` payload.
+pub fn to_payload(event: &RdevEvent) -> Payload {
+    match &event.event_type {
+        EventType::KeyPress(key) => Payload::Key(key_event(InputAction::Press, *key, event)),
+        EventType::KeyRelease(key) => Payload::Key(key_event(InputAction::Release, *key, event)),
+        EventType::MouseMove { x, y } => Payload::MouseMove(MouseMoveEvent { x: *x, y: *y }),
+        EventType::ButtonPress(button) => {
+            Payload::Button(button_event(InputAction::Press, *button))
+        }
+        EventType::ButtonRelease(button) => {
+            Payload::Button(button_event(InputAction::Release, *button))
+        }
+        EventType::Wheel { delta_x, delta_y } => Payload::Wheel(WheelEvent {
+            delta_x: *delta_x,
+            delta_y: *delta_y,
+        }),
+    }
+}
+
+/// Converts a wall-clock time into a proto timestamp.
+pub fn timestamp(time: SystemTime) -> pbjson_types::Timestamp {
+    chrono::DateTime::<chrono::Utc>::from(time).into()
+}
+
+/// Every kind of event the agent can publish.
+pub const ALL_KINDS: [EventKind; 7] = [
+    EventKind::Key,
+    EventKind::Button,
+    EventKind::MouseMove,
+    EventKind::Wheel,
+    EventKind::SystemInfo,
+    EventKind::SystemInfoChange,
+    EventKind::Marker,
+];
+
+/// Parses proto `EventKind` values, rejecting unknown and unspecified kinds.
+pub fn parse_kinds(kinds: &[i32]) -> Result<HashSet<EventKind>, String> {
+    kinds
+        .iter()
+        .map(|&kind| match EventKind::try_from(kind) {
+            Ok(EventKind::Unspecified) | Err(_) => Err(format!("Unsupported event kind: {}", kind)),
+            Ok(kind) => Ok(kind),
+        })
+        .collect()
+}
+
+/// Checks a client marker against the size limits documented in the proto.
+pub fn validate_marker(marker: &Marker) -> Result<(), String> {
+    if marker.name.is_empty() {
+        return Err("Marker name must not be empty".to_string());
+    }
+    if marker.name.len() > MAX_MARKER_NAME_LEN {
+        return Err(format!(
+            "Marker name must be at most {} bytes",
+            MAX_MARKER_NAME_LEN
+        ));
+    }
+    if marker.metadata.len() > MAX_MARKER_METADATA_ENTRIES {
+        return Err(format!(
+            "Marker metadata must have at most {} entries",
+            MAX_MARKER_METADATA_ENTRIES
+        ));
+    }
+    if let Some((key, _)) = marker.metadata.iter().find(|(key, value)| {
+        key.len() > MAX_MARKER_METADATA_LEN || value.len() > MAX_MARKER_METADATA_LEN
+    }) {
+        return Err(format!(
+            "Marker metadata entry {:?} exceeds {} bytes",
+            key, MAX_MARKER_METADATA_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the kind a raw rdev event will have once converted.
+pub fn input_kind(event_type: &EventType) -> EventKind {
+    match event_type {
+        EventType::KeyPress(_) | EventType::KeyRelease(_) => EventKind::Key,
+        EventType::ButtonPress(_) | EventType::ButtonRelease(_) => EventKind::Button,
+        EventType::MouseMove { .. } => EventKind::MouseMove,
+        EventType::Wheel { .. } => EventKind::Wheel,
+    }
+}
+
+/// Returns the kind used to filter and count events carrying `payload`.
+pub fn kind_of(payload: &Payload) -> EventKind {
+    match payload {
+        Payload::Key(_) => EventKind::Key,
+        Payload::MouseMove(_) => EventKind::MouseMove,
+        Payload::Button(_) => EventKind::Button,
+        Payload::Wheel(_) => EventKind::Wheel,
+        Payload::SystemInfo(_) => EventKind::SystemInfo,
+        Payload::SystemInfoChange(_) => EventKind::SystemInfoChange,
+        Payload::Marker(_) => EventKind::Marker,
+    }
+}
+
+/// Whether `kind` is high-volume captured input, as opposed to the rare
+/// system and marker events.
+pub fn is_input(kind: EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Key | EventKind::Button | EventKind::MouseMove | EventKind::Wheel
+    )
+}
+
+fn key_event(action: InputAction, key: Key, event: &RdevEvent) -> KeyEvent {
+    let (code, raw_code) = key_code(key);
+    KeyEvent {
+        action: action as i32,
+        key: code as i32,
+        raw_code,
+        text: event.name.clone().unwrap_or_default(),
+    }
+}
+
+fn button_event(action: InputAction, button: Button) -> ButtonEvent {
+    let (button, raw_code) = match button {
+        Button::Left => (MouseButton::Left, 0),
+        Button::Right => (MouseButton::Right, 0),
+        Button::Middle => (MouseButton::Middle, 0),
+        Button::Unknown(code) => (MouseButton::Unknown, u32::from(code)),
+    };
+    ButtonEvent {
+        action: action as i32,
+        button: button as i32,
+        raw_code,
+    }
+}
+
+/// Maps an rdev key onto the proto `KeyCode`, returning the platform code for unknown keys.
+fn key_code(key: Key) -> (KeyCode, u32) {
+    let code = match key {
+        Key::Unknown(raw) => return (KeyCode::Unknown, raw),
+        Key::Alt => KeyCode::Alt,
+        Key::AltGr => KeyCode::AltGr,
+        Key::Backspace => KeyCode::Backspace,
+        Key::CapsLock => KeyCode::CapsLock,
+        Key::ControlLeft => KeyCode::ControlLeft,
+        Key::ControlRight => KeyCode::ControlRight,
+        Key::Delete => KeyCode::Delete,
+        Key::DownArrow => KeyCode::DownArrow,
+        Key::End => KeyCode::End,
+        Key::Escape => KeyCode::Escape,
+        Key::F1 => KeyCode::F1,
+        Key::F10 => KeyCode::F10,
+        Key::F11 => KeyCode::F11,
+        Key::F12 => KeyCode::F12,
+        Key::F2 => KeyCode::F2,
+        Key::F3 => KeyCode::F3,
+        Key::F4 => KeyCode::F4,
+        Key::F5 => KeyCode::F5,
+        Key::F6 => KeyCode::F6,
+        Key::F7 => KeyCode::F7,
+        Key::F8 => KeyCode::F8,
+        Key::F9 => KeyCode::F9,
+        Key::Home => KeyCode::Home,
+        Key::LeftArrow => KeyCode::LeftArrow,
+        Key::MetaLeft => KeyCode::MetaLeft,
+        Key::MetaRight => KeyCode::MetaRight,
+        Key::PageDown => KeyCode::PageDown,
+        Key::PageUp => KeyCode::PageUp,
+        Key::Return => KeyCode::Return,
+        Key::RightArrow => KeyCode::RightArrow,
+        Key::ShiftLeft => KeyCode::ShiftLeft,
+        Key::ShiftRight => KeyCode::ShiftRight,
+        Key::Space => KeyCode::Space,
+        Key::Tab => KeyCode::Tab,
+        Key::UpArrow => KeyCode::UpArrow,
+        Key::PrintScreen => KeyCode::PrintScreen,
+        Key::ScrollLock => KeyCode::ScrollLock,
+        Key::Pause => KeyCode::Pause,
+        Key::NumLock => KeyCode::NumLock,
+        Key::BackQuote => KeyCode::BackQuote,
+        Key::Num1 => KeyCode::Num1,
+        Key::Num2 => KeyCode::Num2,
+        Key::Num3 => KeyCode::Num3,
+        Key::Num4 => KeyCode::Num4,
+        Key::Num5 => KeyCode::Num5,
+        Key::Num6 => KeyCode::Num6,
+        Key::Num7 => KeyCode::Num7,
+        Key::Num8 => KeyCode::Num8,
+        Key::Num9 => KeyCode::Num9,
+        Key::Num0 => KeyCode::Num0,
+        Key::Minus => KeyCode::Minus,
+        Key::Equal => KeyCode::Equal,
+        Key::KeyQ => KeyCode::KeyQ,
+        Key::KeyW => KeyCode::KeyW,
+        Key::KeyE => KeyCode::KeyE,
+        Key::KeyR => KeyCode::KeyR,
+        Key::KeyT => KeyCode::KeyT,
+        Key::KeyY => KeyCode::KeyY,
+        Key::KeyU => KeyCode::KeyU,
+        Key::KeyI => KeyCode::KeyI,
+        Key::KeyO => KeyCode::KeyO,
+        Key::KeyP => KeyCode::KeyP,
+        Key::LeftBracket => KeyCode::LeftBracket,
+        Key::RightBracket => KeyCode::RightBracket,
+        Key::KeyA => KeyCode::KeyA,
+        Key::KeyS => KeyCode::KeyS,
+        Key::KeyD => KeyCode::KeyD,
+        Key::KeyF => KeyCode::KeyF,
+        Key::KeyG => KeyCode::KeyG,
+        Key::KeyH => KeyCode::KeyH,
+        Key::KeyJ => KeyCode::KeyJ,
+        Key::KeyK => KeyCode::KeyK,
+        Key::KeyL => KeyCode::KeyL,
+        Key::SemiColon => KeyCode::SemiColon,
+        Key::Quote => KeyCode::Quote,
+        Key::BackSlash => KeyCode::BackSlash,
+        Key::IntlBackslash => KeyCode::IntlBackslash,
+        Key::KeyZ => KeyCode::KeyZ,
+        Key::KeyX => KeyCode::KeyX,
+        Key::KeyC => KeyCode::KeyC,
+        Key::KeyV => KeyCode::KeyV,
+        Key::KeyB => KeyCode::KeyB,
+        Key::KeyN => KeyCode::KeyN,
+        Key::KeyM => KeyCode::KeyM,
+        Key::Comma => KeyCode::Comma,
+        Key::Dot => KeyCode::Dot,
+        Key::Slash => KeyCode::Slash,
+        Key::Insert => KeyCode::Insert,
+        Key::KpReturn => KeyCode::KpReturn,
+        Key::KpMinus => KeyCode::KpMinus,
+        Key::KpPlus => KeyCode::KpPlus,
+        Key::KpMultiply => KeyCode::KpMultiply,
+        Key::KpDivide => KeyCode::KpDivide,
+        Key::Kp0 => KeyCode::Kp0,
+        Key::Kp1 => KeyCode::Kp1,
+        Key::Kp2 => KeyCode::Kp2,
+        Key::Kp3 => KeyCode::Kp3,
+        Key::Kp4 => KeyCode::Kp4,
+        Key::Kp5 => KeyCode::Kp5,
+        Key::Kp6 => KeyCode::Kp6,
+        Key::Kp7 => KeyCode::Kp7,
+        Key::Kp8 => KeyCode::Kp8,
+        Key::Kp9 => KeyCode::Kp9,
+        Key::KpDelete => KeyCode::KpDelete,
+        Key::Function => KeyCode::Function,
+    };
+    (code, 0)
+}
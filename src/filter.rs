@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+
+use crate::capture::event::Payload;
+use crate::capture::{Event, EventFilter, EventKind, KeyCode, StreamState};
+use crate::events;
+
+/// Server-side view of a subscriber's `EventFilter`, tracking the state
+/// needed for MouseMove sampling.
+pub struct SubscriberFilter {
+    /// `None` delivers every kind
+    kinds: Option<HashSet<EventKind>>,
+    mouse_move_min_interval_ns: u64,
+    redact_keys: bool,
+    last_mouse_move_ns: Option<u64>,
+}
+
+impl SubscriberFilter {
+    pub fn new(filter: Option<EventFilter>) -> Result<Self, String> {
+        let filter = filter.unwrap_or_default();
+        let kinds = events::parse_kinds(&filter.kinds)?;
+
+        let mouse_move_min_interval_ns = match filter.mouse_move_max_rate {
+            0 => 0,
+            rate => 1_000_000_000 / u64::from(rate),
+        };
+
+        Ok(SubscriberFilter {
+            kinds: (!kinds.is_empty()).then_some(kinds),
+            mouse_move_min_interval_ns,
+            redact_keys: filter.redact_keys,
+            last_mouse_move_ns: None,
+        })
+    }
+
+    /// Returns the event as it should be delivered to this subscriber, or
+    /// `None` if it is filtered out.
+    pub fn apply(&mut self, mut event: Event) -> Option<Event> {
+        let payload = event.payload.as_mut()?;
+
+        if !self.includes(events::kind_of(payload)) {
+            return None;
+        }
+
+        match payload {
+            Payload::MouseMove(_) if self.mouse_move_min_interval_ns > 0 => {
+                // Sample on the event's own monotonic time so bursts queued in
+                // the channel are thinned the same way as live ones
+                if let Some(last) = self.last_mouse_move_ns {
+                    if event.monotonic_ns.saturating_sub(last) < self.mouse_move_min_interval_ns {
+                        return None;
+                    }
+                }
+                self.last_mouse_move_ns = Some(event.monotonic_ns);
+            }
+            Payload::Key(key) if self.redact_keys => {
+                key.key = KeyCode::Unspecified as i32;
+                key.raw_code = 0;
+                key.text.clear();
+            }
+            _ => {}
+        }
+
+        Some(event)
+    }
+
+    /// Returns the state as it should be delivered to this subscriber,
+    /// dropping the parts its events would not carry.
+    pub fn apply_state(&self, mut state: StreamState) -> StreamState {
+        if !self.includes(EventKind::SystemInfo) {
+            state.system_info = None;
+        }
+        if !self.includes(EventKind::Key) {
+            state.pressed_keys.clear();
+        }
+        if !self.includes(EventKind::Button) {
+            state.pressed_buttons.clear();
+        }
+        if self.redact_keys {
+            for key in &mut state.pressed_keys {
+                key.key = KeyCode::Unspecified as i32;
+                key.raw_code = 0;
+                key.text.clear();
+            }
+        }
+        state
+    }
+
+    fn includes(&self, kind: EventKind) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{InputAction, KeyEvent};
+    use crate::test_support::{marker, mouse_move};
+
+    fn event(payload: Payload, monotonic_ns: u64) -> Event {
+        Event {
+            monotonic_ns,
+            payload: Some(payload),
+            ..Default::default()
+        }
+    }
+
+    fn key_press() -> KeyEvent {
+        KeyEvent {
+            action: InputAction::Press as i32,
+            key: KeyCode::Unknown as i32,
+            raw_code: 42,
+            text: "a".to_string(),
+        }
+    }
+
+    #[test]
+    fn delivers_only_requested_kinds() {
+        let mut filter = SubscriberFilter::new(Some(EventFilter {
+            kinds: vec![EventKind::Marker as i32],
+            ..Default::default()
+        }))
+        .unwrap();
+
+        assert!(filter.apply(event(Payload::Key(key_press()), 0)).is_none());
+        assert!(filter.apply(event(marker(), 0)).is_some());
+    }
+
+    #[test]
+    fn rejects_unspecified_kind() {
+        let filter = SubscriberFilter::new(Some(EventFilter {
+            kinds: vec![EventKind::Unspecified as i32],
+            ..Default::default()
+        }));
+        assert!(filter.is_err());
+    }
+
+    #[test]
+    fn samples_mouse_moves_by_event_time() {
+        // 100 per second keeps at most one move every 10ms
+        let mut filter = SubscriberFilter::new(Some(EventFilter {
+            mouse_move_max_rate: 100,
+            ..Default::default()
+        }))
+        .unwrap();
+
+        let delivered: Vec<u64> = [0, 4_000_000, 9_999_999, 10_000_000, 25_000_000]
+            .into_iter()
+            .filter_map(|ns| filter.apply(event(mouse_move(), ns)))
+            .map(|event| event.monotonic_ns)
+            .collect();
+        assert_eq!(delivered, [0, 10_000_000, 25_000_000]);
+    }
+
+    #[test]
+    fn redacts_keys() {
+        let mut filter = SubscriberFilter::new(Some(EventFilter {
+            redact_keys: true,
+            ..Default::default()
+        }))
+        .unwrap();
+
+        let Some(Payload::Key(key)) = filter
+            .apply(event(Payload::Key(key_press()), 0))
+            .unwrap()
+            .payload
+        else {
+            panic!("expected a key event");
+        };
+        assert_eq!(key.action, InputAction::Press as i32);
+        assert_eq!(key.key, KeyCode::Unspecified as i32);
+        assert_eq!(key.raw_code, 0);
+        assert!(key.text.is_empty());
+    }
+
+    #[test]
+    fn state_drops_kinds_not_requested() {
+        let filter = SubscriberFilter::new(Some(EventFilter {
+            kinds: vec![EventKind::Button as i32],
+            ..Default::default()
+        }))
+        .unwrap();
+        let state = filter.apply_state(StreamState {
+            pressed_keys: vec![key_press()],
+            ..Default::default()
+        });
+        assert!(state.pressed_keys.is_empty());
+    }
+}
@@ -0,0 +1,456 @@
+//! HTTP/JSON front end for browser tools that cannot speak native gRPC.
+//!
+//! Every endpoint calls the same `CaptureService` methods as a gRPC client
+//! would, and bodies use the protobuf JSON mapping of the proto messages.
+//! Event streams are also offered over WebSocket, see `websocket`.
+//!
+//! Browsers let any page send simple requests to a local port, so calls from
+//! pages whose origin is not allowed are refused, and POSTs must be JSON,
+//! which a page on another origin can only send after a CORS preflight.
+//! Tokens are checked as for gRPC when the agent runs with `--auth`.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{Extension, Query, State};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE, ORIGIN};
+use axum::http::{HeaderMap, HeaderValue, Method, Request as HttpRequest, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_core::Stream;
+use serde::Deserialize;
+use tokio_stream::StreamExt;
+use tonic::{Code, Request, Status};
+use tower_http::cors::CorsLayer;
+
+use crate::auth::{self, Scope, TokenAuth};
+use crate::capture::capture_service_server::CaptureService;
+use crate::capture::stream_message::Kind;
+use crate::capture::{
+    AgentStatus, Capabilities, Empty, EventFilter, EventKind, GetSystemInfoRequest,
+    InsertMarkerResponse, Marker, StartRequest, StartResponse, StopResponse, StreamMessage,
+    StreamRequest, SystemInfo,
+};
+use crate::{grpc_web, websocket, MyCaptureService};
+
+/// Binds the gateway to `addr`, returning the bound address, which differs
+/// for port 0, and a future serving requests until the process exits. Pages
+/// from `origins` may call it.
+pub fn bind(
+    addr: SocketAddr,
+    service: MyCaptureService,
+    origins: &[HeaderValue],
+    auth: TokenAuth,
+) -> Result<(SocketAddr, impl Future<Output = Result<(), String>>), String> {
+    let cors = CorsLayer::new()
+        .allow_origin(grpc_web::allow_origin(origins))
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([CONTENT_TYPE, AUTHORIZATION]);
+    let app = Router::new()
+        .route("/v1/capture/start", post(start))
+        .route("/v1/capture/stop", post(stop))
+        .route("/v1/status", get(status))
+        .route("/v1/system-info", get(system_info))
+        .route("/v1/capabilities", get(capabilities))
+        .route("/v1/markers", post(insert_marker))
+        .route("/v1/events", get(events))
+        .route("/v1/ws", get(websocket::upgrade))
+        .layer(middleware::from_fn_with_state(auth, authenticate))
+        .layer(middleware::from_fn_with_state(
+            Arc::<[HeaderValue]>::from(origins),
+            check_request,
+        ))
+        .layer(cors)
+        .with_state(service);
+
+    let server = axum::Server::try_bind(&addr)
+        .map_err(|e| format!("Failed to bind HTTP gateway to {}: {}", addr, e))?
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+    let local_addr = server.local_addr();
+    Ok((local_addr, async move {
+        server
+            .await
+            .map_err(|e| format!("HTTP gateway failed: {}", e))
+    }))
+}
+
+/// Refuses calls from pages whose origin is not allowed, and POSTs that are
+/// not JSON. Clients other than browsers send no `Origin`.
+async fn check_request<B>(
+    State(origins): State<Arc<[HeaderValue]>>,
+    request: HttpRequest<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(origin) = request.headers().get(ORIGIN) {
+        if !origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin)
+        {
+            let message = format!("Origin {:?} is not allowed", origin);
+            return ApiError(Status::permission_denied(message)).into_response();
+        }
+    }
+    if request.method() == Method::POST && !is_json(request.headers()) {
+        return error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Code::InvalidArgument,
+            "Expected Content-Type: application/json",
+        );
+    }
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    access_token: Option<String>,
+}
+
+/// Records the caller's scope for the handlers. `EventSource` and WebSocket
+/// clients cannot set headers, so the token may also be passed as an
+/// `access_token` query parameter.
+async fn authenticate<B>(
+    State(auth): State<TokenAuth>,
+    mut request: HttpRequest<B>,
+    next: Next<B>,
+) -> Response {
+    let query = Query::<TokenQuery>::try_from_uri(request.uri()).ok();
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(auth::bearer_token)
+        .or_else(|| query.as_ref()?.access_token.as_deref());
+    match auth.authorize(token) {
+        Ok(scope) => {
+            request.extensions_mut().insert(scope);
+            next.run(request).await
+        }
+        Err(e) => ApiError(Status::unauthenticated(e)).into_response(),
+    }
+}
+
+/// Wraps `message` for a `CaptureService` call made with `scope`.
+fn scoped<T>(scope: Scope, message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    request.extensions_mut().insert(scope);
+    request
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// A gRPC status rendered as an HTTP error with a JSON body.
+pub struct ApiError(Status);
+
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        ApiError(status)
+    }
+}
+
+/// Malformed bodies and query strings get the same JSON error body as
+/// failed calls, rather than axum's plain-text rejection.
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError(Status::invalid_argument(rejection.body_text()))
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError(Status::invalid_argument(rejection.body_text()))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self.0.code() {
+            Code::InvalidArgument | Code::OutOfRange | Code::FailedPrecondition => {
+                StatusCode::BAD_REQUEST
+            }
+            Code::NotFound => StatusCode::NOT_FOUND,
+            Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Code::PermissionDenied => StatusCode::FORBIDDEN,
+            Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        error_response(status, self.0.code(), self.0.message())
+    }
+}
+
+fn error_response(status: StatusCode, code: Code, message: &str) -> Response {
+    let body = serde_json::json!({
+        "code": format!("{:?}", code),
+        "message": message,
+    });
+    (status, Json(body)).into_response()
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+async fn start(
+    State(service): State<MyCaptureService>,
+    Extension(scope): Extension<Scope>,
+    body: Bytes,
+) -> ApiResult<StartResponse> {
+    // The body is optional; an empty one starts with the defaults
+    let request = if body.is_empty() {
+        StartRequest::default()
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|e| Status::invalid_argument(format!("Invalid StartRequest: {}", e)))?
+    };
+    Ok(Json(
+        service.start(scoped(scope, request)).await?.into_inner(),
+    ))
+}
+
+async fn stop(
+    State(service): State<MyCaptureService>,
+    Extension(scope): Extension<Scope>,
+) -> ApiResult<StopResponse> {
+    Ok(Json(
+        service.stop(scoped(scope, Empty {})).await?.into_inner(),
+    ))
+}
+
+async fn status(
+    State(service): State<MyCaptureService>,
+    Extension(scope): Extension<Scope>,
+) -> ApiResult<AgentStatus> {
+    Ok(Json(
+        service
+            .get_status(scoped(scope, Empty {}))
+            .await?
+            .into_inner(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct SystemInfoQuery {
+    #[serde(default)]
+    refresh: bool,
+}
+
+async fn system_info(
+    State(service): State<MyCaptureService>,
+    Extension(scope): Extension<Scope>,
+    query: Result<Query<SystemInfoQuery>, QueryRejection>,
+) -> ApiResult<SystemInfo> {
+    let Query(query) = query?;
+    let request = GetSystemInfoRequest {
+        refresh: query.refresh,
+    };
+    Ok(Json(
+        service
+            .get_system_info(scoped(scope, request))
+            .await?
+            .into_inner(),
+    ))
+}
+
+async fn capabilities(
+    State(service): State<MyCaptureService>,
+    Extension(scope): Extension<Scope>,
+) -> ApiResult<Capabilities> {
+    Ok(Json(
+        service
+            .get_capabilities(scoped(scope, Empty {}))
+            .await?
+            .into_inner(),
+    ))
+}
+
+async fn insert_marker(
+    State(service): State<MyCaptureService>,
+    Extension(scope): Extension<Scope>,
+    marker: Result<Json<Marker>, JsonRejection>,
+) -> ApiResult<InsertMarkerResponse> {
+    let Json(marker) = marker?;
+    Ok(Json(
+        service
+            .insert_marker(scoped(scope, marker))
+            .await?
+            .into_inner(),
+    ))
+}
+
+/// Query parameters of event streams, mirroring `StreamRequest`.
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    /// Comma separated kinds, e.g. "key,mouse_move"
+    kinds: Option<String>,
+    #[serde(default)]
+    mouse_move_max_rate: u32,
+    #[serde(default)]
+    redact_keys: bool,
+    resume_after: Option<u64>,
+    #[serde(default)]
+    initial_state: bool,
+}
+
+/// Streams `StreamMessage`s as Server-Sent Events. Events carry their
+/// sequence number as the SSE id, so a reconnecting `EventSource` resumes
+/// through `Last-Event-ID` without losing events.
+async fn events(
+    State(service): State<MyCaptureService>,
+    Extension(scope): Extension<Scope>,
+    query: Result<Query<EventsQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>, ApiError> {
+    let Query(query) = query?;
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    let request = query
+        .stream_request(last_event_id)
+        .map_err(Status::invalid_argument)?;
+    let mut messages = service
+        .stream_events(scoped(scope, request))
+        .await?
+        .into_inner();
+
+    let output = async_stream::stream! {
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(message) => yield Ok(sse_event(&message)),
+                Err(status) => {
+                    yield Ok(SseEvent::default().event("error").data(status.message()));
+                    break;
+                }
+            }
+        }
+    };
+    Ok(Sse::new(output).keep_alive(KeepAlive::default()))
+}
+
+impl EventsQuery {
+    /// Builds the `StreamRequest` for these parameters. `last_event_id`, sent
+    /// by reconnecting clients, takes precedence over `resume_after`.
+    pub fn stream_request(&self, last_event_id: Option<u64>) -> Result<StreamRequest, String> {
+        let kinds = match &self.kinds {
+            Some(kinds) => parse_kinds(kinds)?,
+            None => Vec::new(),
+        };
+        Ok(StreamRequest {
+            filter: Some(EventFilter {
+                kinds,
+                mouse_move_max_rate: self.mouse_move_max_rate,
+                redact_keys: self.redact_keys,
+            }),
+            resume_after_sequence: last_event_id.or(self.resume_after),
+            batch: None,
+            // A resuming client already has the state from its first connection
+            initial_state: self.initial_state && last_event_id.is_none(),
+        })
+    }
+}
+
+fn sse_event(message: &StreamMessage) -> SseEvent {
+    let event = SseEvent::default()
+        .json_data(message)
+        .unwrap_or_else(|e| SseEvent::default().event("error").data(e.to_string()));
+    match &message.kind {
+        Some(Kind::Event(event_message)) => event.id(event_message.sequence.to_string()),
+        _ => event,
+    }
+}
+
+/// Parses kind names as used in the proto, with or without the
+/// `EVENT_KIND_` prefix and in any case.
+fn parse_kinds(kinds: &str) -> Result<Vec<i32>, String> {
+    kinds
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let name = name.to_uppercase();
+            let name = name.strip_prefix("EVENT_KIND_").unwrap_or(&name);
+            EventKind::from_str_name(&format!("EVENT_KIND_{}", name))
+                .filter(|kind| *kind != EventKind::Unspecified)
+                .map(|kind| kind as i32)
+                .ok_or_else(|| format!("Unsupported event kind: {}", name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// Answers every GET and POST that passes `check_request` with 200.
+    async fn check(origins: &[&str], request: HttpRequest<Body>) -> StatusCode {
+        let origins: Arc<[HeaderValue]> = origins
+            .iter()
+            .map(|origin| HeaderValue::from_str(origin).unwrap())
+            .collect();
+        let app = Router::new()
+            .route("/", get(|| async {}).post(|| async {}))
+            .layer(middleware::from_fn_with_state(origins, check_request));
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    fn get_from(origin: Option<&str>) -> HttpRequest<Body> {
+        let mut request = HttpRequest::get("/");
+        if let Some(origin) = origin {
+            request = request.header(ORIGIN, origin);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    fn post_as(content_type: Option<&str>) -> HttpRequest<Body> {
+        let mut request = HttpRequest::post("/");
+        if let Some(content_type) = content_type {
+            request = request.header(CONTENT_TYPE, content_type);
+        }
+        request.body(Body::from("{}")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn allows_listed_origins_and_non_browser_clients() {
+        let origins = ["http://localhost:5173"];
+        let allowed = get_from(Some("http://localhost:5173"));
+        assert_eq!(check(&origins, allowed).await, StatusCode::OK);
+        assert_eq!(check(&origins, get_from(None)).await, StatusCode::OK);
+        let any = get_from(Some("http://example.com"));
+        assert_eq!(check(&["*"], any).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn refuses_unlisted_origins() {
+        let origins = ["http://localhost:5173"];
+        let unlisted = get_from(Some("http://evil.example"));
+        assert_eq!(check(&origins, unlisted).await, StatusCode::FORBIDDEN);
+        let no_origins = get_from(Some("http://localhost:5173"));
+        assert_eq!(check(&[], no_origins).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn requires_json_posts() {
+        let json = post_as(Some("application/json; charset=utf-8"));
+        assert_eq!(check(&[], json).await, StatusCode::OK);
+        for content_type in [Some("text/plain"), None] {
+            assert_eq!(
+                check(&[], post_as(content_type)).await,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            );
+        }
+    }
+}
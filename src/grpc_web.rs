@@ -0,0 +1,72 @@
+//! gRPC-Web on the main gRPC port, so the Electron renderer can use the
+//! generated web client without a hop through the main process.
+
+use std::time::Duration;
+
+use http::header::HeaderName;
+use http::{HeaderValue, Method};
+use tonic_web::GrpcWebLayer;
+use tower::layer::util::{Identity, Stack};
+use tower::ServiceBuilder;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// How long browsers may cache a preflight response.
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Trailers the web client reads to learn the call status.
+const EXPOSED_HEADERS: [&str; 3] = ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+
+/// Request headers sent by grpc-web clients, plus the auth token.
+const ALLOWED_HEADERS: [&str; 5] = [
+    "x-grpc-web",
+    "content-type",
+    "x-user-agent",
+    "grpc-timeout",
+    "authorization",
+];
+
+pub type GrpcWebStack = ServiceBuilder<Stack<GrpcWebLayer, Stack<CorsLayer, Identity>>>;
+
+/// Builds the gRPC-Web translation layer, answering CORS requests from
+/// `origins` only. Returns `None` when no origin is configured, which keeps
+/// the server HTTP/2-only.
+pub fn layer(origins: &[HeaderValue]) -> Option<GrpcWebStack> {
+    if origins.is_empty() {
+        return None;
+    }
+
+    let cors = CorsLayer::new()
+        .allow_origin(allow_origin(origins))
+        .allow_methods([Method::POST])
+        .allow_headers(ALLOWED_HEADERS.map(HeaderName::from_static))
+        .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static))
+        .max_age(PREFLIGHT_MAX_AGE);
+
+    Some(ServiceBuilder::new().layer(cors).layer(GrpcWebLayer::new()))
+}
+
+/// CORS origins for `origins`, where "*" allows any.
+pub fn allow_origin(origins: &[HeaderValue]) -> AllowOrigin {
+    if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.iter().cloned())
+    }
+}
+
+/// Parses an `--grpc-web-origin` or `--http-origin` value such as `http://localhost:5173`.
+/// Browsers send origins without a trailing slash, so one is dropped, except
+/// from `file://` which Electron sends as is.
+pub fn parse_origin(origin: &str) -> Result<HeaderValue, String> {
+    let origin = match origin.strip_suffix('/') {
+        Some(trimmed) if !origin.ends_with("://") => trimmed,
+        _ => origin,
+    };
+    if origin != "*" && origin != "null" && !origin.contains("://") {
+        return Err(format!(
+            "expected an origin such as http://localhost:5173, got {:?}",
+            origin
+        ));
+    }
+    HeaderValue::from_str(origin).map_err(|e| e.to_string())
+}
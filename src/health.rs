@@ -0,0 +1,57 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::capture::{ListenerStatus, SystemScanStatus};
+use crate::events;
+
+/// Liveness and timing of the agent's background work, reported by GetStatus.
+pub struct AgentHealth {
+    pub started_at: SystemTime,
+    started: Instant,
+    listener_error: Mutex<Option<String>>,
+    last_scan: Mutex<Option<SystemScanStatus>>,
+}
+
+impl AgentHealth {
+    pub fn new() -> Self {
+        AgentHealth {
+            started_at: SystemTime::now(),
+            started: Instant::now(),
+            listener_error: Mutex::new(None),
+            last_scan: Mutex::new(None),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Records why the input listener stopped.
+    pub fn listener_failed(&self, error: String) {
+        *self.listener_error.lock().unwrap() = Some(error);
+    }
+
+    pub fn listener_status(&self, alive: bool) -> ListenerStatus {
+        ListenerStatus {
+            alive,
+            error: self
+                .listener_error
+                .lock()
+                .unwrap()
+                .clone()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn record_scan(&self, started_at: SystemTime, duration: Duration, error: Option<String>) {
+        *self.last_scan.lock().unwrap() = Some(SystemScanStatus {
+            started_at: Some(events::timestamp(started_at)),
+            duration_ms: duration.as_millis() as u64,
+            error: error.unwrap_or_default(),
+        });
+    }
+
+    pub fn last_scan(&self) -> Option<SystemScanStatus> {
+        self.last_scan.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,332 @@
+//! Accepts gRPC connections on TCP, terminating TLS when configured, and on
+//! a Unix domain socket, and records who is on the other end so handlers can
+//! authorize and log by peer.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::Connected;
+use tonic::Request;
+
+use crate::tls::{ClientIdentity, ServerTls};
+
+/// Handshakes that take longer are abandoned, so a stalled client cannot
+/// hold a connection slot.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connection details attached to every request on a connection.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub address: PeerAddress,
+    /// Set when the client presented a certificate the agent accepted
+    pub client: Option<ClientIdentity>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PeerAddress {
+    Tcp(SocketAddr),
+    /// Credentials of the process that connected to the Unix socket
+    #[cfg(unix)]
+    Unix {
+        uid: u32,
+        gid: u32,
+        pid: Option<i32>,
+    },
+}
+
+impl PeerInfo {
+    pub fn of<T>(request: &Request<T>) -> Option<&PeerInfo> {
+        request.extensions().get::<PeerInfo>()
+    }
+
+    /// Describes the peer of `request` for logs and subscriber lists.
+    pub fn describe<T>(request: &Request<T>) -> String {
+        PeerInfo::of(request)
+            .map(|peer| peer.to_string())
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for PeerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.address)?;
+        if let Some(client) = &self.client {
+            write!(f, " ({})", client)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddress::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            PeerAddress::Unix { uid, gid, pid } => {
+                write!(f, "unix:uid={},gid={}", uid, gid)?;
+                if let Some(pid) = pid {
+                    write!(f, ",pid={}", pid)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+enum Transport {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+/// An accepted connection, ready for HTTP/2.
+pub struct Connection {
+    transport: Transport,
+    peer: PeerInfo,
+}
+
+impl Connected for Connection {
+    type ConnectInfo = PeerInfo;
+
+    fn connect_info(&self) -> PeerInfo {
+        self.peer.clone()
+    }
+}
+
+/// Merges the connections accepted by every listener into one stream for
+/// the server. Each listener is served by its own task.
+pub struct Listeners {
+    sender: mpsc::Sender<io::Result<Connection>>,
+    receiver: mpsc::Receiver<io::Result<Connection>>,
+}
+
+impl Listeners {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel(16);
+        Listeners { sender, receiver }
+    }
+
+    /// Accepts TCP connections. TLS handshakes run on their own tasks so a
+    /// slow client does not hold up others.
+    pub fn add_tcp(&self, listener: TcpListener, tls: Option<Arc<ServerTls>>) {
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            while !sender.is_closed() {
+                let (stream, remote_addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        accept_failed(e).await;
+                        continue;
+                    }
+                };
+                stream.set_nodelay(true).ok();
+
+                let sender = sender.clone();
+                let tls = tls.clone();
+                tokio::spawn(async move {
+                    let connection = match tls {
+                        Some(tls) => match handshake(&tls, stream, remote_addr).await {
+                            Ok(connection) => connection,
+                            Err(e) => {
+                                eprintln!(
+                                    "[WARN] TLS handshake with {} failed: {}",
+                                    remote_addr, e
+                                );
+                                return;
+                            }
+                        },
+                        None => Connection {
+                            transport: Transport::Plain(stream),
+                            peer: PeerInfo {
+                                address: PeerAddress::Tcp(remote_addr),
+                                client: None,
+                            },
+                        },
+                    };
+                    sender.send(Ok(connection)).await.ok();
+                });
+            }
+        });
+    }
+
+    /// Accepts Unix socket connections, only from processes running as
+    /// `owner` when given.
+    #[cfg(unix)]
+    pub fn add_unix(&self, listener: tokio::net::UnixListener, owner: Option<u32>) {
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            while !sender.is_closed() {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        accept_failed(e).await;
+                        continue;
+                    }
+                };
+                let address = match stream.peer_cred() {
+                    Ok(cred) => PeerAddress::Unix {
+                        uid: cred.uid(),
+                        gid: cred.gid(),
+                        pid: cred.pid(),
+                    },
+                    Err(e) => {
+                        eprintln!("[WARN] Failed to read Unix socket peer credentials: {}", e);
+                        continue;
+                    }
+                };
+                if let (Some(owner), PeerAddress::Unix { uid, .. }) = (owner, &address) {
+                    if *uid != owner {
+                        eprintln!("[WARN] Rejected Unix socket connection from {}", address);
+                        continue;
+                    }
+                }
+                let connection = Connection {
+                    transport: Transport::Unix(stream),
+                    peer: PeerInfo {
+                        address,
+                        client: None,
+                    },
+                };
+                if sender.send(Ok(connection)).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    pub fn into_stream(self) -> ReceiverStream<io::Result<Connection>> {
+        ReceiverStream::new(self.receiver)
+    }
+}
+
+async fn accept_failed(e: io::Error) {
+    // Usually out of file descriptors; give some time to free up
+    eprintln!("[ERROR] Failed to accept connection: {}", e);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+async fn handshake(
+    tls: &ServerTls,
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+) -> Result<Connection, String> {
+    let stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, tls.acceptor.accept(stream))
+        .await
+        .map_err(|_| "timed out".to_string())?
+        .map_err(|e| e.to_string())?;
+    let client = tls.client_identity(stream.get_ref().1.peer_certificates());
+    if let Some(client) = &client {
+        println!(
+            "[INFO] Client {} connected from {}, SHA-256 fingerprint {}",
+            client, remote_addr, client.fingerprint
+        );
+    }
+    Ok(Connection {
+        transport: Transport::Tls(Box::new(stream)),
+        peer: PeerInfo {
+            address: PeerAddress::Tcp(remote_addr),
+            client,
+        },
+    })
+}
+
+/// Binds a Unix socket at `path` with permissions `mode`, replacing a
+/// socket left behind by an earlier run. A socket another process still
+/// accepts connections on is left alone. Returns the listener and the uid
+/// owning the socket, which is the agent's.
+#[cfg(unix)]
+pub fn bind_unix(
+    path: &std::path::Path,
+    mode: u32,
+) -> Result<(tokio::net::UnixListener, u32), String> {
+    use std::fs;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path.display()));
+        }
+        match std::os::unix::net::UnixStream::connect(path) {
+            Ok(_) => return Err(format!("{} is in use by another process", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+            Err(e) => return Err(format!("Failed to probe {}: {}", path.display(), e)),
+        }
+        fs::remove_file(path)
+            .map_err(|e| format!("Failed to remove stale socket {}: {}", path.display(), e))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .map_err(|e| format!("Failed to bind {}: {}", path.display(), e))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .map_err(|e| format!("Failed to set permissions of {}: {}", path.display(), e))?;
+    let owner = fs::metadata(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .uid();
+    Ok((listener, owner))
+}
+
+/// Parses a file mode in octal, e.g. 600 or 0660.
+pub fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("expected an octal file mode such as 600, got {:?}", value))
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().transport {
+            Transport::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Transport::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Transport::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().transport {
+            Transport::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Transport::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Transport::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().transport {
+            Transport::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Transport::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Transport::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().transport {
+            Transport::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Transport::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Transport::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -2,60 +2,122 @@ use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use clap::Parser;
 use futures_core::Stream;
-use tokio::sync::broadcast;
-use tonic::{transport::Server, Request, Response, Status};
+use tonic::{transport::Server, Code, Request, Response, Status, Streaming};
+use tower::util::option_layer;
 
 use capture::capture_service_server::{CaptureService, CaptureServiceServer};
-use capture::{Empty, Event, Status as RpcStatus};
+use capture::command_ack::Result as AckResult;
+use capture::event::Payload;
+use capture::session_command::Command;
+use capture::session_message::Kind as SessionKind;
+use capture::system_info_update::Kind as UpdateKind;
+use capture::{
+    AgentStatus, Capabilities, CommandAck, Empty, EventKind, EventKindCount, GetSystemInfoRequest,
+    InsertMarkerResponse, Marker, SessionCommand, SessionMessage, StartRequest, StartResponse,
+    StopResponse, StreamMessage, StreamRequest, SubscriberList, SystemInfoChange,
+    SystemInfoChangeKind, SystemInfoFieldChange, SystemInfoSnapshot, SystemInfoUpdate,
+    UpdateConfigRequest, WatchSystemInfoRequest,
+};
 
-use chrono::Local;
 use rdev::{listen, Button, Event as RdevEvent, EventType, Key};
 use std::cell::RefCell;
 use std::collections::HashSet;
-use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
-
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Mutex};
+
+mod announce;
+mod auth;
+mod batch;
+mod broadcaster;
+mod capabilities;
+mod config;
+mod events;
+mod filter;
+mod gateway;
+mod grpc_web;
+mod health;
+mod listener;
+mod options;
+mod private_file;
+mod session;
+mod subscription;
 mod system_info;
+#[cfg(test)]
+mod test_support;
+mod tls;
+mod websocket;
+use announce::Endpoints;
+use auth::{Scope, TokenAuth, Tokens};
+use batch::BatchLimits;
+use broadcaster::{EventBroadcaster, ReplayLimits};
+use config::CaptureConfig;
+use filter::SubscriberFilter;
+use health::AgentHealth;
+use listener::{Listeners, PeerInfo};
+use options::Options;
+use session::CaptureSession;
+use subscription::{StreamStart, SubscriberRegistry, Subscription};
 use system_info::SystemInfo;
 
 pub mod capture {
-    tonic::include_proto!("capture");
+    pub mod v1 {
+        tonic::include_proto!("capture.v1");
+        include!(concat!(env!("OUT_DIR"), "/capture.v1.serde.rs"));
+    }
+
+    // The newest package, used throughout the agent
+    pub use v1::*;
 }
 
+#[derive(Clone)]
 pub struct MyCaptureService {
-    broadcaster: broadcast::Sender<Event>,
+    broadcaster: Arc<EventBroadcaster>,
     capturing: Arc<AtomicBool>,
-    system_info: Arc<Mutex<Option<SystemInfo>>>,
+    /// Latest scanned system information, watched by WatchSystemInfo streams
+    system_info: watch::Sender<Option<SystemInfo>>,
+    subscribers: Arc<SubscriberRegistry>,
+    config: watch::Sender<CaptureConfig>,
+    health: Arc<AgentHealth>,
+    listener_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    session: Arc<Mutex<Option<CaptureSession>>>,
 }
 
-#[tonic::async_trait]
-impl CaptureService for MyCaptureService {
-    async fn start(&self, _: Request<Empty>) -> Result<Response<RpcStatus>, Status> {
+impl MyCaptureService {
+    async fn start_capture(&self, request: StartRequest) -> StartResponse {
+        let mut current = self.session.lock().await;
+        if let Some(session) = current.as_ref() {
+            return StartResponse {
+                message: "Already started".into(),
+                session: Some(session.to_proto()),
+            };
+        }
+
+        let (dropped, _) = self.subscribers.drop_totals();
+        let session = CaptureSession::new(request, dropped);
+        self.broadcaster.begin_session(session.id.clone());
         self.capturing.store(true, Ordering::Relaxed);
-        println!("[INFO] Event capturing started");
+        println!("[INFO] Event capturing started (session {})", session.id);
 
         // Collect and send system information in a separate task
         let broadcaster = self.broadcaster.clone();
         let system_info = self.system_info.clone();
+        let health = self.health.clone();
+        let publish = self.config.borrow().is_enabled(EventKind::SystemInfo);
         tokio::spawn(async move {
-            match SystemInfo::collect() {
+            match scan_system_info(&health).await {
                 Ok(info) => {
-                    let system_event = Event {
-                        name: "SystemInfo".to_string(),
-                        timestamp: Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
-                        details: info.to_formatted_string(),
-                    };
+                    if publish {
+                        broadcaster.publish(Payload::SystemInfo(Box::new(SystemInfoSnapshot {
+                            info: Some(info.to_proto()),
+                        })));
+                    }
 
                     // Store system info for change monitoring
-                    *system_info.lock().await = Some(info);
-
-                    if let Err(e) = broadcaster.send(system_event) {
-                        // Only log if it's not a "no receivers" error
-                        if !e.to_string().contains("channel closed") {
-                            eprintln!("[ERROR] Failed to send system info: {}", e);
-                        }
-                    }
+                    system_info.send_replace(Some(info));
                 }
                 Err(e) => {
                     eprintln!("[ERROR] Failed to collect system info: {}", e);
@@ -63,150 +125,523 @@ impl CaptureService for MyCaptureService {
             }
         });
 
-        Ok(Response::new(RpcStatus {
+        let response = StartResponse {
             message: "Started".into(),
-        }))
+            session: Some(session.to_proto()),
+        };
+        *current = Some(session);
+        response
     }
 
-    async fn stop(&self, _: Request<Empty>) -> Result<Response<RpcStatus>, Status> {
+    async fn stop_capture(&self) -> StopResponse {
+        let mut current = self.session.lock().await;
+        let Some(session) = current.take() else {
+            return StopResponse {
+                message: "Not started".into(),
+                summary: None,
+            };
+        };
+
         self.capturing.store(false, Ordering::Relaxed);
-        println!("[INFO] Event capturing stopped");
-        Ok(Response::new(RpcStatus {
+        let tally = self.broadcaster.end_session();
+        let (dropped, _) = self.subscribers.drop_totals();
+        println!("[INFO] Event capturing stopped (session {})", session.id);
+
+        StopResponse {
             message: "Stopped".into(),
-        }))
+            summary: Some(session.summarize(tally, dropped)),
+        }
+    }
+
+    /// Returns the cached system information, scanning first if there is
+    /// none yet or `refresh` is set.
+    async fn system_snapshot(&self, refresh: bool) -> Result<capture::SystemInfo, String> {
+        if !refresh {
+            if let Some(info) = self.system_info.borrow().as_ref() {
+                return Ok(info.to_proto());
+            }
+        }
+
+        let info = scan_system_info(&self.health)
+            .await
+            .map_err(|e| format!("Failed to collect system info: {}", e))?;
+        let snapshot = info.to_proto();
+        self.system_info.send_replace(Some(info));
+        Ok(snapshot)
+    }
+
+    async fn listener_alive(&self) -> bool {
+        self.listener_handle
+            .lock()
+            .await
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+    }
+
+    /// Opens a subscription as requested by a `StreamEvents` client.
+    fn subscribe(
+        &self,
+        request: StreamRequest,
+        peer: String,
+    ) -> Result<Subscription, (Code, String)> {
+        let filter =
+            SubscriberFilter::new(request.filter).map_err(|e| (Code::InvalidArgument, e))?;
+        let batch = BatchLimits::new(request.batch).map_err(|e| (Code::InvalidArgument, e))?;
+        let start = match (request.resume_after_sequence, request.initial_state) {
+            (Some(_), true) => {
+                return Err((
+                    Code::InvalidArgument,
+                    "initial_state cannot be combined with resume_after_sequence".to_string(),
+                ))
+            }
+            (Some(sequence), false) => StreamStart::ResumeAfter(sequence),
+            (None, true) => StreamStart::WithState(
+                self.system_info
+                    .borrow()
+                    .as_ref()
+                    .map(|info| Box::new(info.to_proto())),
+            ),
+            (None, false) => StreamStart::Live,
+        };
+        Subscription::new(
+            &self.broadcaster,
+            &self.subscribers,
+            filter,
+            batch,
+            start,
+            peer,
+        )
+        .map_err(|e| (Code::OutOfRange, e))
+    }
+
+    /// Publishes a client marker and returns its sequence number.
+    fn publish_marker(&self, marker: Marker) -> Result<u64, String> {
+        events::validate_marker(&marker)?;
+        println!("[INFO] Marker inserted: {}", marker.name);
+        Ok(self.broadcaster.publish(Payload::Marker(marker)))
+    }
+
+    /// Runs one command received on a `Session` stream opened with `scope`.
+    async fn run_command(
+        &self,
+        command: SessionCommand,
+        subscription: &mut Subscription,
+        scope: Scope,
+    ) -> CommandAck {
+        let controls = matches!(
+            command.command,
+            Some(Command::Start(_) | Command::Stop(_) | Command::InsertMarker(_))
+        );
+        let result = match command.command {
+            _ if controls && scope < Scope::Control => Err((
+                Code::PermissionDenied,
+                "This command requires the control token".to_string(),
+            )),
+            Some(Command::Start(request)) => {
+                Ok(Some(AckResult::Start(self.start_capture(request).await)))
+            }
+            Some(Command::Stop(_)) => Ok(Some(AckResult::Stop(self.stop_capture().await))),
+            Some(Command::UpdateFilter(filter)) => SubscriberFilter::new(Some(filter))
+                .map(|filter| {
+                    subscription.set_filter(filter);
+                    None
+                })
+                .map_err(|e| (Code::InvalidArgument, e)),
+            Some(Command::InsertMarker(marker)) => self
+                .publish_marker(marker)
+                .map(|sequence| Some(AckResult::MarkerSequence(sequence)))
+                .map_err(|e| (Code::InvalidArgument, e)),
+            Some(Command::RequestSnapshot(request)) => self
+                .system_snapshot(request.refresh)
+                .await
+                .map(|info| Some(AckResult::Snapshot(Box::new(info))))
+                .map_err(|e| (Code::Internal, e)),
+            None => Err((Code::InvalidArgument, "Missing command".to_string())),
+        };
+
+        match result {
+            Ok(result) => CommandAck {
+                command_id: command.command_id,
+                result,
+                ..Default::default()
+            },
+            Err((code, error)) => CommandAck {
+                command_id: command.command_id,
+                code: code as i32,
+                error,
+                result: None,
+            },
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl CaptureService for MyCaptureService {
+    async fn start(
+        &self,
+        request: Request<StartRequest>,
+    ) -> Result<Response<StartResponse>, Status> {
+        auth::require_control(&request).map_err(Status::permission_denied)?;
+        if let Some(peer) = PeerInfo::of(&request) {
+            println!("[INFO] Start requested by {}", peer);
+        }
+        Ok(Response::new(
+            self.start_capture(request.into_inner()).await,
+        ))
+    }
+
+    async fn stop(&self, request: Request<Empty>) -> Result<Response<StopResponse>, Status> {
+        auth::require_control(&request).map_err(Status::permission_denied)?;
+        if let Some(peer) = PeerInfo::of(&request) {
+            println!("[INFO] Stop requested by {}", peer);
+        }
+        Ok(Response::new(self.stop_capture().await))
     }
 
     type StreamEventsStream =
-        Pin<Box<dyn Stream<Item = Result<Event, Status>> + Send + Sync + 'static>>;
+        Pin<Box<dyn Stream<Item = Result<StreamMessage, Status>> + Send + Sync + 'static>>;
 
     async fn stream_events(
         &self,
-        _: Request<Empty>,
+        request: Request<StreamRequest>,
     ) -> Result<Response<Self::StreamEventsStream>, Status> {
-        let mut rx = self.broadcaster.subscribe();
+        let peer = PeerInfo::describe(&request);
+        let mut subscription = self
+            .subscribe(request.into_inner(), peer)
+            .map_err(|(code, e)| Status::new(code, e))?;
         let output = async_stream::stream! {
-            while let Ok(event) = rx.recv().await {
-                yield Ok(event);
+            while let Some(message) = subscription.next().await {
+                yield Ok(message);
             }
             println!("[WARN] Event stream ended");
         };
         Ok(Response::new(Box::pin(output) as Self::StreamEventsStream))
     }
-}
 
-fn format_event_details(event: &RdevEvent) -> String {
-    match &event.event_type {
-        EventType::KeyPress(key) => format!("{:?}", key),
-        EventType::KeyRelease(key) => format!("{:?}", key),
-        EventType::MouseMove { x, y } => format!("{},{}", x, y),
-        EventType::ButtonPress(button) => format!("{:?}", button),
-        EventType::ButtonRelease(button) => format!("{:?}", button),
-        EventType::Wheel { delta_x, delta_y } => format!("dx={},dy={}", delta_x, delta_y),
+    async fn list_subscribers(
+        &self,
+        _: Request<Empty>,
+    ) -> Result<Response<SubscriberList>, Status> {
+        Ok(Response::new(SubscriberList {
+            subscribers: self
+                .subscribers
+                .list()
+                .iter()
+                .map(|stats| stats.to_proto())
+                .collect(),
+        }))
     }
-}
 
-fn compare_system_info(old: &SystemInfo, new: &SystemInfo) -> String {
-    let mut changes = Vec::new();
-
-    if old.system_info.directx_version != new.system_info.directx_version {
-        changes.push(format!(
-            "DirectX version changed: {} -> {}",
-            old.system_info.directx_version, new.system_info.directx_version
-        ));
+    async fn get_system_info(
+        &self,
+        request: Request<GetSystemInfoRequest>,
+    ) -> Result<Response<capture::SystemInfo>, Status> {
+        self.system_snapshot(request.into_inner().refresh)
+            .await
+            .map(Response::new)
+            .map_err(Status::internal)
     }
 
-    if old.system_info.os_version != new.system_info.os_version {
-        changes.push(format!(
-            "OS version changed: {} -> {}",
-            old.system_info.os_version, new.system_info.os_version
-        ));
+    async fn get_config(
+        &self,
+        _: Request<Empty>,
+    ) -> Result<Response<capture::CaptureConfig>, Status> {
+        Ok(Response::new(self.config.borrow().to_proto()))
     }
 
-    if old.system_info.memory_mb != new.system_info.memory_mb {
-        changes.push(format!(
-            "Memory changed: {} MB -> {} MB",
-            old.system_info.memory_mb, new.system_info.memory_mb
-        ));
-    }
+    async fn update_config(
+        &self,
+        request: Request<UpdateConfigRequest>,
+    ) -> Result<Response<capture::CaptureConfig>, Status> {
+        auth::require_control(&request).map_err(Status::permission_denied)?;
+        let update = request.into_inner();
+        let mut result = Ok(());
+        self.config
+            .send_if_modified(|config| match config.with_update(&update) {
+                Ok(updated) => {
+                    *config = updated;
+                    true
+                }
+                Err(e) => {
+                    result = Err(e);
+                    false
+                }
+            });
+        result.map_err(Status::invalid_argument)?;
 
-    if old.network_info.local_ip != new.network_info.local_ip {
-        changes.push(format!(
-            "Local IP changed: {} -> {}",
-            old.network_info.local_ip, new.network_info.local_ip
-        ));
+        let config = self.config.borrow().to_proto();
+        println!("[INFO] Capture config updated: {:?}", config);
+        Ok(Response::new(config))
     }
 
-    if old.network_info.public_ip != new.network_info.public_ip {
-        changes.push(format!(
-            "Public IP changed: {} -> {}",
-            old.network_info.public_ip, new.network_info.public_ip
-        ));
+    async fn get_status(&self, _: Request<Empty>) -> Result<Response<AgentStatus>, Status> {
+        let listener_alive = self.listener_alive().await;
+        let published = self.broadcaster.published_counts();
+        let (events_dropped, lag_count) = self.subscribers.drop_totals();
+        let session = self.session.lock().await;
+
+        Ok(Response::new(AgentStatus {
+            capturing: self.capturing.load(Ordering::Relaxed),
+            capture_started_at: session.as_ref().map(|s| events::timestamp(s.started_at)),
+            agent_started_at: Some(events::timestamp(self.health.started_at)),
+            uptime_ms: self.health.uptime().as_millis() as u64,
+            subscriber_count: self.subscribers.count() as u32,
+            events_published: events::ALL_KINDS
+                .into_iter()
+                .map(|kind| EventKindCount {
+                    kind: kind as i32,
+                    count: published.get(&kind).copied().unwrap_or_default(),
+                })
+                .collect(),
+            last_sequence: self.broadcaster.last_sequence(),
+            events_dropped,
+            lag_count,
+            last_system_scan: self.health.last_scan(),
+            listener: Some(self.health.listener_status(listener_alive)),
+            session: session.as_ref().map(|s| s.to_proto()),
+        }))
     }
 
-    // Check for USB device changes
-    if old.usb_input_devices.len() != new.usb_input_devices.len() {
-        changes.push(format!(
-            "USB devices count changed: {} -> {}",
-            old.usb_input_devices.len(),
-            new.usb_input_devices.len()
-        ));
+    async fn insert_marker(
+        &self,
+        request: Request<Marker>,
+    ) -> Result<Response<InsertMarkerResponse>, Status> {
+        auth::require_control(&request).map_err(Status::permission_denied)?;
+        let sequence = self
+            .publish_marker(request.into_inner())
+            .map_err(Status::invalid_argument)?;
+        Ok(Response::new(InsertMarkerResponse { sequence }))
     }
 
-    // Check for monitor changes
-    if old.monitors.len() != new.monitors.len() {
-        changes.push(format!(
-            "Monitor count changed: {} -> {}",
-            old.monitors.len(),
-            new.monitors.len()
-        ));
+    type WatchSystemInfoStream =
+        Pin<Box<dyn Stream<Item = Result<SystemInfoUpdate, Status>> + Send + 'static>>;
+
+    async fn watch_system_info(
+        &self,
+        _: Request<WatchSystemInfoRequest>,
+    ) -> Result<Response<Self::WatchSystemInfoStream>, Status> {
+        // Watch before making sure a snapshot is cached, so no scan is missed
+        let mut updates = self.system_info.subscribe();
+        self.system_snapshot(false)
+            .await
+            .map_err(Status::internal)?;
+        let mut last = updates
+            .borrow_and_update()
+            .clone()
+            .ok_or_else(|| Status::internal("System info is not available"))?;
+
+        let output = async_stream::stream! {
+            yield Ok(SystemInfoUpdate {
+                kind: Some(UpdateKind::Snapshot(Box::new(last.to_proto()))),
+            });
+            // Diff against what this stream has sent, so changes from scans
+            // replaced before the client read them are still reported
+            while updates.changed().await.is_ok() {
+                let Some(current) = updates.borrow_and_update().clone() else {
+                    continue;
+                };
+                let changes = compare_system_info(&last, &current);
+                last = current;
+                if !changes.is_empty() {
+                    yield Ok(SystemInfoUpdate {
+                        kind: Some(UpdateKind::Change(SystemInfoChange { changes })),
+                    });
+                }
+            }
+            println!("[WARN] System info watch ended");
+        };
+        Ok(Response::new(
+            Box::pin(output) as Self::WatchSystemInfoStream
+        ))
     }
 
-    // Check for video card changes
-    if old.video_cards.len() != new.video_cards.len() {
-        changes.push(format!(
-            "Video cards count changed: {} -> {}",
-            old.video_cards.len(),
-            new.video_cards.len()
-        ));
+    async fn get_capabilities(&self, _: Request<Empty>) -> Result<Response<Capabilities>, Status> {
+        let listener_alive = self.listener_alive().await;
+        Ok(Response::new(capabilities::capabilities(
+            &self.config.borrow(),
+            listener_alive,
+        )))
     }
 
-    // Check for PCI device changes
-    if old.pci_devices.len() != new.pci_devices.len() {
-        changes.push(format!(
-            "PCI devices count changed: {} -> {}",
-            old.pci_devices.len(),
-            new.pci_devices.len()
-        ));
+    type SessionStream =
+        Pin<Box<dyn Stream<Item = Result<SessionMessage, Status>> + Send + 'static>>;
+
+    async fn session(
+        &self,
+        request: Request<Streaming<SessionCommand>>,
+    ) -> Result<Response<Self::SessionStream>, Status> {
+        let peer = PeerInfo::describe(&request);
+        let scope = auth::scope(&request);
+        let mut commands = request.into_inner();
+        // Subscribe before reading any command, so events caused by a
+        // command are always delivered on this stream
+        let filter = SubscriberFilter::new(None).map_err(Status::internal)?;
+        let mut subscription = Subscription::new(
+            &self.broadcaster,
+            &self.subscribers,
+            filter,
+            None,
+            StreamStart::Live,
+            peer,
+        )
+        .map_err(Status::internal)?;
+        let service = self.clone();
+
+        let output = async_stream::stream! {
+            // Keep delivering events after the client half-closes its side
+            let mut commands_open = true;
+            loop {
+                tokio::select! {
+                    message = subscription.next() => match message {
+                        Some(message) => yield Ok(SessionMessage {
+                            kind: Some(SessionKind::Stream(message)),
+                        }),
+                        None => break,
+                    },
+                    command = commands.message(), if commands_open => match command {
+                        Ok(Some(command)) => {
+                            let ack = service.run_command(command, &mut subscription, scope).await;
+                            yield Ok(SessionMessage {
+                                kind: Some(SessionKind::Ack(ack)),
+                            });
+                        }
+                        Ok(None) => commands_open = false,
+                        Err(status) => {
+                            yield Err(status);
+                            break;
+                        }
+                    },
+                }
+            }
+            println!("[WARN] Session stream ended");
+        };
+        Ok(Response::new(Box::pin(output) as Self::SessionStream))
     }
+}
 
-    changes.join("\n")
+fn compare_system_info(old: &SystemInfo, new: &SystemInfo) -> Vec<SystemInfoFieldChange> {
+    let mut changes = Vec::new();
+    let mut check = |path: &str, old_value: String, new_value: String| {
+        if old_value != new_value {
+            let kind = if old_value.is_empty() {
+                SystemInfoChangeKind::Added
+            } else if new_value.is_empty() {
+                SystemInfoChangeKind::Removed
+            } else {
+                SystemInfoChangeKind::Modified
+            };
+            changes.push(SystemInfoFieldChange {
+                path: path.to_string(),
+                old_value,
+                new_value,
+                kind: kind as i32,
+            });
+        }
+    };
+
+    check(
+        "system_info.directx_version",
+        old.system_info.directx_version.clone(),
+        new.system_info.directx_version.clone(),
+    );
+    check(
+        "system_info.os_version",
+        old.system_info.os_version.clone(),
+        new.system_info.os_version.clone(),
+    );
+    check(
+        "system_info.memory_mb",
+        old.system_info.memory_mb.to_string(),
+        new.system_info.memory_mb.to_string(),
+    );
+    check(
+        "network_info.local_ip",
+        old.network_info.local_ip.clone(),
+        new.network_info.local_ip.clone(),
+    );
+    check(
+        "network_info.public_ip",
+        old.network_info.public_ip.clone(),
+        new.network_info.public_ip.clone(),
+    );
+
+    // Device lists are compared by count only
+    check(
+        "usb_input_devices.count",
+        old.usb_input_devices.len().to_string(),
+        new.usb_input_devices.len().to_string(),
+    );
+    check(
+        "monitors.count",
+        old.monitors.len().to_string(),
+        new.monitors.len().to_string(),
+    );
+    check(
+        "video_cards.count",
+        old.video_cards.len().to_string(),
+        new.video_cards.len().to_string(),
+    );
+    check(
+        "pci_devices.count",
+        old.pci_devices.len().to_string(),
+        new.pci_devices.len().to_string(),
+    );
+
+    changes
+}
+
+/// Collects system information, recording the scan's timing and outcome for GetStatus.
+async fn scan_system_info(health: &AgentHealth) -> Result<SystemInfo, String> {
+    let started_at = SystemTime::now();
+    let started = Instant::now();
+    let result = SystemInfo::collect_async().await;
+    health.record_scan(
+        started_at,
+        started.elapsed(),
+        result.as_ref().err().cloned(),
+    );
+    result
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = Options::parse();
+
     // --- Print system info on startup ---
-    match system_info::SystemInfo::collect() {
+    let startup_system_info = match system_info::SystemInfo::collect() {
         Ok(info) => {
             println!("--- System Info at Startup ---");
             println!("{}", info.to_formatted_string());
             println!("------------------------------");
+            Some(info)
         }
         Err(e) => {
             eprintln!("[ERROR] Failed to collect system info at startup: {}", e);
+            None
         }
-    }
+    };
     // -------------------------------------
 
-    let (broadcaster, _) = broadcast::channel(1024);
+    // Input lane sized for bursts of mouse movement; system and marker events are rare
+    let broadcaster = Arc::new(EventBroadcaster::new(
+        1024,
+        256,
+        ReplayLimits {
+            max_events: 10_000,
+            max_age: Duration::from_secs(120),
+        },
+    ));
     let capturing = Arc::new(AtomicBool::new(false)); // Start with capturing off until client connects
     let listener_handle = Arc::new(Mutex::new(None));
+    let health = Arc::new(AgentHealth::new());
 
-    let mouse_move_interval = Arc::new(Mutex::new(0.05f64)); // in seconds
+    let (config, _) = watch::channel(CaptureConfig::default());
 
     {
-        let tx = broadcaster.clone();
+        let broadcaster = Arc::clone(&broadcaster);
         let capturing_clone = Arc::clone(&capturing);
-        let mouse_move_interval_clone = Arc::clone(&mouse_move_interval);
+        let config = config.subscribe();
+        let health = Arc::clone(&health);
 
         let pressed_keys = RefCell::new(HashSet::<Key>::new());
         let pressed_buttons = RefCell::new(HashSet::<Button>::new());
@@ -221,131 +656,205 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     return;
                 }
 
-                let (event_name, is_new_event) = match &event.event_type {
+                let config = config.borrow();
+                let should_send = match &event.event_type {
                     EventType::KeyPress(key) => {
-                        let mut keys = pressed_keys.borrow_mut();
-                        if keys.contains(key) {
-                            (None, false)
-                        } else {
-                            keys.insert(*key);
-                            (Some("KeyPress"), true)
-                        }
+                        // Ignore auto-repeat while the key is held down, unless configured not to
+                        pressed_keys.borrow_mut().insert(*key) || !config.suppress_key_repeats
                     }
                     EventType::KeyRelease(key) => {
                         pressed_keys.borrow_mut().remove(key);
-                        (Some("KeyRelease"), true)
-                    }
-                    EventType::ButtonPress(button) => {
-                        let mut buttons = pressed_buttons.borrow_mut();
-                        if buttons.contains(button) {
-                            (None, false)
-                        } else {
-                            buttons.insert(*button);
-                            (Some("MouseButtonPress"), true)
-                        }
+                        true
                     }
+                    EventType::ButtonPress(button) => pressed_buttons.borrow_mut().insert(*button),
                     EventType::ButtonRelease(button) => {
                         pressed_buttons.borrow_mut().remove(button);
-                        (Some("MouseButtonRelease"), true)
+                        true
                     }
                     EventType::MouseMove { .. } => {
                         let now = Instant::now();
                         let mut last_time = last_mouse_move_time.borrow_mut();
-                        // Read the interval (locked on each event)
-                        let interval = *mouse_move_interval_clone.blocking_lock();
-                        if now.duration_since(*last_time).as_secs_f64() >= interval {
+                        if now.duration_since(*last_time) >= config.mouse_move_interval {
                             *last_time = now;
-                            (Some("MouseMove"), true)
+                            true
                         } else {
-                            (None, false)
+                            false
                         }
                     }
-                    EventType::Wheel { .. } => (Some("MouseWheel"), true),
+                    EventType::Wheel { .. } => true,
                 };
 
-                if let Some(name) = event_name {
-                    if is_new_event {
-                        let now = Local::now();
-                        let event_timestamp = now.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
-                        let event_details = format_event_details(&event);
-                        let cap_event = Event {
-                            name: name.to_string(),
-                            timestamp: event_timestamp,
-                            details: event_details,
-                        };
-                        if let Err(e) = tx.send(cap_event) {
-                            // Only log if it's not a "no receivers" error
-                            if !e.to_string().contains("channel closed") {
-                                eprintln!("[ERROR] Failed to send event: {}", e);
-                            }
-                        }
-                    }
+                // Pressed state is tracked above even for disabled kinds, so
+                // re-enabling them doesn't report stale presses
+                if should_send && config.is_enabled(events::input_kind(&event.event_type)) {
+                    broadcaster.publish_at(events::to_payload(&event), event.time);
                 }
             };
 
             if let Err(e) = listen(callback) {
                 eprintln!("[ERROR] Error in event listener: {:?}", e);
+                health.listener_failed(format!("{:?}", e));
             }
         });
 
         *listener_handle.lock().await = Some(handle);
     }
 
+    let (system_info, _) = watch::channel(startup_system_info);
+
     // Add system monitoring thread
     {
-        let tx = broadcaster.clone();
+        let broadcaster = Arc::clone(&broadcaster);
         let capturing_clone = Arc::clone(&capturing);
+        let mut config = config.subscribe();
+        let health = Arc::clone(&health);
+        let system_info = system_info.clone();
         tokio::spawn(async move {
             let mut last_system_info: Option<SystemInfo> = None;
 
             loop {
-                if capturing_clone.load(Ordering::Relaxed) {
-                    if let Ok(current_info) = SystemInfo::collect() {
+                let last_scan = tokio::time::Instant::now();
+                // Watchers get changes whether or not input is being captured
+                let capturing = capturing_clone.load(Ordering::Relaxed);
+                if capturing || system_info.receiver_count() > 0 {
+                    if let Ok(current_info) = scan_system_info(&health).await {
                         if let Some(ref last_info) = last_system_info {
                             // Check for changes and send only changed values
                             let changes = compare_system_info(last_info, &current_info);
-                            if !changes.is_empty() {
-                                let change_event = Event {
-                                    name: "SystemInfoChange".to_string(),
-                                    timestamp: Local::now()
-                                        .format("%Y-%m-%d %H:%M:%S%.3f")
-                                        .to_string(),
-                                    details: changes,
-                                };
-
-                                if let Err(e) = tx.send(change_event) {
-                                    // Only log if it's not a "no receivers" error
-                                    if !e.to_string().contains("channel closed") {
-                                        eprintln!(
-                                            "[ERROR] Failed to send system info change: {}",
-                                            e
-                                        );
-                                    }
-                                }
+                            let enabled = config.borrow().is_enabled(EventKind::SystemInfoChange);
+                            if !changes.is_empty() && enabled && capturing {
+                                broadcaster.publish(Payload::SystemInfoChange(SystemInfoChange {
+                                    changes,
+                                }));
                             }
                         }
+                        system_info.send_replace(Some(current_info.clone()));
                         last_system_info = Some(current_info);
                     }
                 }
 
-                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                // Wait for the next scan, rescheduling if the poll interval changes
+                loop {
+                    let next_scan = last_scan + config.borrow_and_update().system_poll_interval;
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(next_scan) => break,
+                        changed = config.changed() => {
+                            if changed.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
             }
         });
     }
 
-    let addr = "127.0.0.1:50051".parse()?;
     let service = MyCaptureService {
         broadcaster,
         capturing,
-        system_info: Arc::new(Mutex::new(None)),
+        system_info,
+        subscribers: Arc::new(SubscriberRegistry::default()),
+        config,
+        health,
+        listener_handle,
+        session: Arc::new(Mutex::new(None)),
+    };
+
+    let mut endpoints = Endpoints {
+        pid: std::process::id(),
+        ..Default::default()
+    };
+    // A file left by a run that was killed would point at the wrong ports
+    if let Some(path) = &options.port_file {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(format!("Failed to remove {}: {}", path.display(), e).into());
+            }
+            _ => {}
+        }
+    }
+
+    let auth = if options.auth.auth {
+        let tokens = Tokens::generate()?;
+        tokens.publish(options.auth.auth_token_file.as_deref())?;
+        TokenAuth::new(Some(tokens))
+    } else {
+        TokenAuth::new(None)
     };
 
-    println!("[INFO] gRPC server listening on {}", addr);
+    if let Some(http_addr) = options.http_addr {
+        let (http_addr, gateway) = gateway::bind(
+            http_addr,
+            service.clone(),
+            &options.http_origins,
+            auth.clone(),
+        )?;
+        tokio::spawn(async move {
+            if let Err(e) = gateway.await {
+                eprintln!("[ERROR] {}", e);
+            }
+        });
+        println!("[INFO] HTTP gateway listening on {}", http_addr);
+        endpoints.http = Some(http_addr);
+    }
 
     let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel();
+    // gRPC-Web requests arrive over HTTP/1.1
+    let grpc_web = grpc_web::layer(&options.grpc_web_origins);
+    if grpc_web.is_some() {
+        println!(
+            "[INFO] Accepting gRPC-Web from {:?}",
+            options.grpc_web_origins
+        );
+    }
+    let tls = tls::server_tls(&options.tls, grpc_web.is_some())?;
+    if let Some(tls) = &tls {
+        endpoints.tls_fingerprint = Some(tls.fingerprint.clone());
+        println!(
+            "[INFO] Serving gRPC over TLS with {}, SHA-256 fingerprint {}",
+            tls.cert_path.display(),
+            tls.fingerprint
+        );
+        if tls.client_auth {
+            println!("[INFO] Client certificates required");
+        }
+    }
+
+    let listeners = Listeners::new();
+    if !options.unix.no_tcp {
+        let listener = TcpListener::bind(options.addr)
+            .await
+            .map_err(|e| format!("Failed to bind gRPC server to {}: {}", options.addr, e))?;
+        let addr = listener.local_addr()?;
+        listeners.add_tcp(listener, tls.map(Arc::new));
+        println!("[INFO] gRPC server listening on {}", addr);
+        endpoints.grpc = Some(addr);
+    }
+    if let Some(path) = &options.unix.unix_socket {
+        #[cfg(unix)]
+        {
+            let (listener, owner) = listener::bind_unix(path, options.unix.unix_socket_mode)?;
+            listeners.add_unix(
+                listener,
+                options.unix.unix_socket_owner_only.then_some(owner),
+            );
+            println!("[INFO] gRPC server listening on {}", path.display());
+            endpoints.unix_socket = Some(path.clone());
+        }
+        #[cfg(not(unix))]
+        return Err(format!(
+            "Cannot serve on {}: Unix domain sockets are not supported on this platform",
+            path.display()
+        )
+        .into());
+    }
+    endpoints.announce(options.port_file.as_deref())?;
+
     let server = Server::builder()
-        .add_service(CaptureServiceServer::new(service))
-        .serve_with_shutdown(addr, async {
+        .accept_http1(grpc_web.is_some())
+        .layer(option_layer(grpc_web))
+        .add_service(CaptureServiceServer::with_interceptor(service, auth))
+        .serve_with_incoming_shutdown(listeners.into_stream(), async {
             shutdown_receiver.await.ok();
             println!("[INFO] Shutting down server...");
         });
@@ -358,7 +867,114 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("[INFO] Received CTRL+C, shutting down...");
             shutdown_sender.send(()).ok();
         }
+        _ = terminate_signal() => {
+            println!("[INFO] Received SIGTERM, shutting down...");
+            shutdown_sender.send(()).ok();
+        }
+    }
+
+    if let Some(path) = &options.unix.unix_socket {
+        std::fs::remove_file(path).ok();
+    }
+    if let Some(path) = &options.port_file {
+        std::fs::remove_file(path).ok();
     }
 
     Ok(())
 }
+
+/// Completes on SIGTERM, which is how process managers such as Electron's
+/// `kill()` stop the agent. Never completes elsewhere.
+async fn terminate_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+                return;
+            }
+            Err(e) => eprintln!("[WARN] Failed to install SIGTERM handler: {}", e),
+        }
+    }
+    std::future::pending::<()>().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use system_info::{Monitor, NetworkInfo, ProcessorInfo, SystemInfoCore};
+
+    fn system_info() -> SystemInfo {
+        SystemInfo {
+            system_info: SystemInfoCore {
+                directx_version: "12".to_string(),
+                os_version: "10.0.19045".to_string(),
+                real_os: "Windows 10".to_string(),
+                memory_mb: 16384,
+                physical_model: String::new(),
+                machine_signature: String::new(),
+                user: "player".to_string(),
+                monitor_start_time: String::new(),
+            },
+            pci_devices: Vec::new(),
+            drives: Vec::new(),
+            network_info: NetworkInfo {
+                local_ip: "192.168.1.20".to_string(),
+                public_ip: String::new(),
+            },
+            video_cards: Vec::new(),
+            monitors: vec![Monitor { model: None }],
+            usb_input_devices: Vec::new(),
+            processor_info: ProcessorInfo {
+                cpu_model: String::new(),
+                cpu_cores: 8,
+            },
+        }
+    }
+
+    #[test]
+    fn compare_system_info_classifies_changes() {
+        let old = system_info();
+        let mut new = system_info();
+        new.network_info.public_ip = "203.0.113.7".to_string();
+        new.network_info.local_ip.clear();
+        new.monitors.push(Monitor { model: None });
+
+        let changes: Vec<_> = compare_system_info(&old, &new)
+            .into_iter()
+            .map(|change| {
+                let kind = SystemInfoChangeKind::try_from(change.kind).unwrap();
+                (change.path, change.old_value, change.new_value, kind)
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (
+                    "network_info.local_ip".to_string(),
+                    "192.168.1.20".to_string(),
+                    String::new(),
+                    SystemInfoChangeKind::Removed,
+                ),
+                (
+                    "network_info.public_ip".to_string(),
+                    String::new(),
+                    "203.0.113.7".to_string(),
+                    SystemInfoChangeKind::Added,
+                ),
+                (
+                    "monitors.count".to_string(),
+                    "1".to_string(),
+                    "2".to_string(),
+                    SystemInfoChangeKind::Modified,
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn compare_system_info_ignores_unchanged() {
+        assert!(compare_system_info(&system_info(), &system_info()).is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::{ArgGroup, Args, Parser};
+use http::HeaderValue;
+
+use crate::{grpc_web, listener, tls};
+
+/// Command-line options of the agent. Every listener other than gRPC is off
+/// unless asked for.
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Captures input events and system information over gRPC"
+)]
+pub struct Options {
+    /// Address of the gRPC server. Port 0 lets the OS pick a free port; the
+    /// bound address is announced on stdout
+    #[arg(long, default_value = "127.0.0.1:50051", conflicts_with = "no_tcp")]
+    pub addr: SocketAddr,
+    /// Also write the bound addresses as JSON to this file once listening
+    #[arg(long, value_name = "PATH")]
+    pub port_file: Option<PathBuf>,
+    /// Also serve REST endpoints and a Server-Sent Events stream on this
+    /// address, e.g. 127.0.0.1:50052
+    #[arg(long)]
+    pub http_addr: Option<SocketAddr>,
+    /// Accept HTTP gateway calls from browser pages with this origin; can be
+    /// repeated, and "*" allows any origin. Calls from other pages are refused
+    #[arg(
+        long = "http-origin",
+        value_name = "ORIGIN",
+        value_parser = grpc_web::parse_origin,
+        requires = "http_addr"
+    )]
+    pub http_origins: Vec<HeaderValue>,
+    /// Accept gRPC-Web calls from browser pages with this origin, e.g.
+    /// http://localhost:5173; can be repeated, and "*" allows any origin
+    #[arg(long = "grpc-web-origin", value_name = "ORIGIN", value_parser = grpc_web::parse_origin)]
+    pub grpc_web_origins: Vec<HeaderValue>,
+    #[command(flatten)]
+    pub unix: UnixSocketOptions,
+    #[command(flatten)]
+    pub tls: TlsOptions,
+    #[command(flatten)]
+    pub auth: AuthOptions,
+}
+
+/// Token authentication of gRPC and HTTP gateway calls.
+#[derive(Debug, Args)]
+pub struct AuthOptions {
+    /// Require a per-launch token on every gRPC and HTTP gateway call. The
+    /// read-only and control tokens are printed on stdout unless
+    /// --auth-token-file is given
+    #[arg(long)]
+    pub auth: bool,
+    /// Write the tokens as JSON to this file instead of printing them. On
+    /// Unix it is readable only by the current user
+    #[arg(long, value_name = "PATH", requires = "auth")]
+    pub auth_token_file: Option<PathBuf>,
+}
+
+/// Unix domain socket listener, served alongside or instead of TCP.
+#[derive(Debug, Args)]
+pub struct UnixSocketOptions {
+    /// Also serve gRPC on a Unix domain socket at this path
+    #[arg(long, value_name = "PATH")]
+    pub unix_socket: Option<PathBuf>,
+    /// Permissions of the socket file, in octal
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "600",
+        value_parser = listener::parse_mode,
+        requires = "unix_socket"
+    )]
+    pub unix_socket_mode: u32,
+    /// Reject socket connections from processes of other users
+    #[arg(long, requires = "unix_socket")]
+    pub unix_socket_owner_only: bool,
+    /// Serve gRPC only on the Unix socket
+    #[arg(long, requires = "unix_socket")]
+    pub no_tcp: bool,
+}
+
+/// TLS settings of the gRPC listener.
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("server_cert").args(["tls", "tls_cert"]).multiple(true)))]
+pub struct TlsOptions {
+    /// Serve gRPC over TLS with a self-signed certificate, generated on
+    /// first run and reused afterwards
+    #[arg(long)]
+    pub tls: bool,
+    /// Directory holding the self-signed certificate. Defaults to a
+    /// per-user data directory
+    #[arg(long, value_name = "DIR")]
+    pub tls_dir: Option<PathBuf>,
+    /// Serve gRPC over TLS with this PEM certificate instead
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "tls_key",
+        conflicts_with = "tls_dir"
+    )]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of --tls-cert
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Require client certificates signed by a CA in this PEM file. Refused
+    /// with listeners that cannot check certificates
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "server_cert",
+        conflicts_with_all = ["unix_socket", "http_addr"]
+    )]
+    pub tls_client_ca: Option<PathBuf>,
+    /// Require client certificates with this SHA-256 fingerprint, unless
+    /// signed by --tls-client-ca; can be repeated
+    #[arg(
+        long = "tls-client-fingerprint",
+        value_name = "SHA256",
+        value_parser = tls::parse_fingerprint,
+        requires = "server_cert",
+        conflicts_with_all = ["unix_socket", "http_addr"]
+    )]
+    pub tls_client_fingerprints: Vec<String>,
+}
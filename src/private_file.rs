@@ -0,0 +1,29 @@
+//! Files holding secrets, such as TLS private keys and auth tokens.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Writes `contents` to `path` as a new file only the current user can read,
+/// replacing any existing file so its permissions do not carry over. On
+/// Windows the file inherits the ACL of its directory instead, so it is only
+/// private inside a per-user directory.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(format!("Failed to replace {}: {}", path.display(), e));
+        }
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
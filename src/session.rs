@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::time::{Instant, SystemTime};
+
+use crate::broadcaster::SessionTally;
+use crate::capture as proto;
+use crate::capture::{EventKindCount, SessionSummary, StartRequest};
+use crate::events;
+
+/// A running capture session, from `Start` until `Stop`.
+pub struct CaptureSession {
+    pub id: String,
+    pub label: String,
+    pub metadata: HashMap<String, String>,
+    pub started_at: SystemTime,
+    started: Instant,
+    /// Subscriber drop total when the session started
+    dropped_at_start: u64,
+}
+
+impl CaptureSession {
+    pub fn new(request: StartRequest, dropped_at_start: u64) -> Self {
+        CaptureSession {
+            id: uuid::Uuid::new_v4().to_string(),
+            label: request.label,
+            metadata: request.metadata,
+            started_at: SystemTime::now(),
+            started: Instant::now(),
+            dropped_at_start,
+        }
+    }
+
+    pub fn to_proto(&self) -> proto::CaptureSession {
+        proto::CaptureSession {
+            id: self.id.clone(),
+            label: self.label.clone(),
+            metadata: self.metadata.clone(),
+            started_at: Some(events::timestamp(self.started_at)),
+        }
+    }
+
+    /// Builds the end-of-session report from what was published during it.
+    pub fn summarize(&self, tally: Option<SessionTally>, dropped_now: u64) -> SessionSummary {
+        let (published, system_changes) = tally
+            .map(|tally| (tally.published, tally.system_changes))
+            .unwrap_or_default();
+
+        SessionSummary {
+            session: Some(self.to_proto()),
+            stopped_at: Some(events::timestamp(SystemTime::now())),
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            events_published: events::ALL_KINDS
+                .into_iter()
+                .map(|kind| EventKindCount {
+                    kind: kind as i32,
+                    count: published.get(&kind).copied().unwrap_or_default(),
+                })
+                .collect(),
+            system_changes,
+            events_dropped: dropped_now.saturating_sub(self.dropped_at_start),
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+
+use crate::batch::BatchLimits;
+use crate::broadcaster::{EventBroadcaster, EventReceiver};
+use crate::capture as proto;
+use crate::capture::stream_message::Kind;
+use crate::capture::{Event, EventBatch, EventsDropped, StreamMessage, SubscriberInfo};
+use crate::events;
+use crate::filter::SubscriberFilter;
+
+/// Delivery counters for a single connected subscriber.
+pub struct SubscriberStats {
+    id: u64,
+    peer: String,
+    connected_at: SystemTime,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    lag_count: AtomicU64,
+}
+
+impl SubscriberStats {
+    pub fn to_proto(&self) -> SubscriberInfo {
+        SubscriberInfo {
+            id: self.id,
+            peer: self.peer.clone(),
+            connected_at: Some(events::timestamp(self.connected_at)),
+            events_delivered: self.delivered.load(Ordering::Relaxed),
+            events_dropped: self.dropped.load(Ordering::Relaxed),
+            lag_count: self.lag_count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Tracks every live subscription so its counters can be reported.
+#[derive(Default)]
+pub struct SubscriberRegistry {
+    next_id: AtomicU64,
+    active: Mutex<HashMap<u64, Arc<SubscriberStats>>>,
+    /// Counters carried over from subscribers that have disconnected
+    retired_dropped: AtomicU64,
+    retired_lag_count: AtomicU64,
+}
+
+impl SubscriberRegistry {
+    /// Snapshot of all connected subscribers, ordered by id.
+    pub fn list(&self) -> Vec<Arc<SubscriberStats>> {
+        let mut subscribers: Vec<_> = self.active.lock().unwrap().values().cloned().collect();
+        subscribers.sort_by_key(|s| s.id);
+        subscribers
+    }
+
+    pub fn count(&self) -> usize {
+        self.active.lock().unwrap().len()
+    }
+
+    /// Dropped events and lag incidents summed over every subscriber, past and present.
+    pub fn drop_totals(&self) -> (u64, u64) {
+        let active = self.active.lock().unwrap();
+        let dropped = active
+            .values()
+            .map(|s| s.dropped.load(Ordering::Relaxed))
+            .sum::<u64>();
+        let lag_count = active
+            .values()
+            .map(|s| s.lag_count.load(Ordering::Relaxed))
+            .sum::<u64>();
+        (
+            dropped + self.retired_dropped.load(Ordering::Relaxed),
+            lag_count + self.retired_lag_count.load(Ordering::Relaxed),
+        )
+    }
+
+    fn register(&self, peer: String) -> Arc<SubscriberStats> {
+        let stats = Arc::new(SubscriberStats {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            peer,
+            connected_at: SystemTime::now(),
+            delivered: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            lag_count: AtomicU64::new(0),
+        });
+        self.active
+            .lock()
+            .unwrap()
+            .insert(stats.id, Arc::clone(&stats));
+        stats
+    }
+
+    fn unregister(&self, id: u64) {
+        // Retire counters under the lock so drop_totals never sees them twice or not at all
+        let mut active = self.active.lock().unwrap();
+        if let Some(stats) = active.remove(&id) {
+            self.retired_dropped
+                .fetch_add(stats.dropped.load(Ordering::Relaxed), Ordering::Relaxed);
+            self.retired_lag_count
+                .fetch_add(stats.lag_count.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Where a new subscription's stream begins.
+pub enum StreamStart {
+    /// With the next published event
+    Live,
+    /// Replaying the buffered events after this sequence number
+    ResumeAfter(u64),
+    /// With a `StreamState` carrying this system information
+    WithState(Option<Box<proto::SystemInfo>>),
+}
+
+/// A subscriber's view of the event stream: replays missed events when
+/// resuming, applies its filter, optionally batches events and turns
+/// broadcast overflow into `EventsDropped` markers instead of ending the
+/// stream.
+///
+/// The subscriber is listed in the registry until this value is dropped.
+pub struct Subscription {
+    rx: EventReceiver,
+    /// Buffered events to deliver before any live ones
+    replay: VecDeque<Event>,
+    filter: SubscriberFilter,
+    batch: Option<BatchLimits>,
+    /// Events collected for the next batch. Kept here rather than in `next`
+    /// so a cancelled `next` does not lose them.
+    batched: Vec<Event>,
+    /// When the current batch must be flushed
+    batch_deadline: Option<Instant>,
+    /// Message to deliver before anything else: the initial state, or the
+    /// message that ended the current batch
+    held: Option<StreamMessage>,
+    stats: Arc<SubscriberStats>,
+    registry: Arc<SubscriberRegistry>,
+}
+
+impl Subscription {
+    /// Subscribes to live events, starting as described by `start`. Fails if
+    /// the events to resume after are no longer buffered.
+    pub fn new(
+        broadcaster: &EventBroadcaster,
+        registry: &Arc<SubscriberRegistry>,
+        filter: SubscriberFilter,
+        batch: Option<BatchLimits>,
+        start: StreamStart,
+        peer: String,
+    ) -> Result<Self, String> {
+        let (rx, replay, held) = match start {
+            StreamStart::Live => (broadcaster.subscribe(), Vec::new(), None),
+            StreamStart::ResumeAfter(sequence) => {
+                let (rx, replay) = broadcaster.subscribe_after(sequence)?;
+                (rx, replay, None)
+            }
+            StreamStart::WithState(system_info) => {
+                let (rx, state) = broadcaster.subscribe_with_state();
+                let state = filter.apply_state(proto::StreamState {
+                    system_info: system_info.map(|info| *info),
+                    ..state
+                });
+                let message = StreamMessage {
+                    kind: Some(Kind::State(Box::new(state))),
+                };
+                (rx, Vec::new(), Some(message))
+            }
+        };
+        Ok(Subscription {
+            rx,
+            replay: replay.into(),
+            filter,
+            batch,
+            batched: Vec::new(),
+            batch_deadline: None,
+            held,
+            stats: registry.register(peer),
+            registry: Arc::clone(registry),
+        })
+    }
+
+    /// Applies `filter` to all events delivered from now on, including any
+    /// still waiting to be replayed.
+    pub fn set_filter(&mut self, filter: SubscriberFilter) {
+        self.filter = filter;
+    }
+
+    /// Waits for the next message to deliver. Returns `None` once the
+    /// broadcaster has shut down.
+    ///
+    /// Cancel safe: events already taken off the channel are kept for the
+    /// next call.
+    pub async fn next(&mut self) -> Option<StreamMessage> {
+        if let Some(message) = self.held.take() {
+            return Some(message);
+        }
+        let Some(limits) = self.batch else {
+            return self.next_unbatched().await;
+        };
+
+        while self.batched.len() < limits.max_events {
+            let message = match self.batch_deadline {
+                Some(deadline) => {
+                    match tokio::time::timeout_at(deadline, self.next_unbatched()).await {
+                        Ok(message) => message,
+                        Err(_) => break,
+                    }
+                }
+                None => self.next_unbatched().await,
+            };
+
+            match message {
+                Some(StreamMessage {
+                    kind: Some(Kind::Event(event)),
+                }) => {
+                    if self.batched.is_empty() {
+                        self.batch_deadline = Some(Instant::now() + limits.max_delay);
+                    }
+                    self.batched.push(event);
+                }
+                Some(message) if self.batched.is_empty() => return Some(message),
+                // Anything else is sent right after the events collected before it
+                Some(message) => {
+                    self.held = Some(message);
+                    break;
+                }
+                None if self.batched.is_empty() => return None,
+                // Flush what was collected before the stream ends
+                None => break,
+            }
+        }
+
+        self.batch_deadline = None;
+        Some(StreamMessage {
+            kind: Some(Kind::Batch(EventBatch {
+                events: std::mem::take(&mut self.batched),
+            })),
+        })
+    }
+
+    async fn next_unbatched(&mut self) -> Option<StreamMessage> {
+        while let Some(event) = self.replay.pop_front() {
+            if let Some(message) = self.deliver(event) {
+                return Some(message);
+            }
+        }
+
+        loop {
+            match self.rx.recv().await {
+                Ok(event) => {
+                    if let Some(message) = self.deliver(event) {
+                        return Some(message);
+                    }
+                }
+                Err(RecvError::Lagged(count)) => {
+                    let total = self.stats.dropped.fetch_add(count, Ordering::Relaxed) + count;
+                    self.stats.lag_count.fetch_add(1, Ordering::Relaxed);
+                    println!(
+                        "[WARN] Subscriber {} fell behind, dropped {} events ({} total)",
+                        self.stats.id, count, total
+                    );
+                    return Some(StreamMessage {
+                        kind: Some(Kind::EventsDropped(EventsDropped { count, total })),
+                    });
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    fn deliver(&mut self, event: Event) -> Option<StreamMessage> {
+        let event = self.filter.apply(event)?;
+        self.stats.delivered.fetch_add(1, Ordering::Relaxed);
+        Some(StreamMessage {
+            kind: Some(Kind::Event(event)),
+        })
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.registry.unregister(self.stats.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_support::{broadcaster, publish_markers};
+
+    fn batched(
+        broadcaster: &EventBroadcaster,
+        max_events: usize,
+        max_delay: Duration,
+        start: StreamStart,
+    ) -> Subscription {
+        Subscription::new(
+            broadcaster,
+            &Arc::new(SubscriberRegistry::default()),
+            SubscriberFilter::new(None).unwrap(),
+            Some(BatchLimits {
+                max_events,
+                max_delay,
+            }),
+            start,
+            "test".to_string(),
+        )
+        .unwrap()
+    }
+
+    fn batch_sequences(message: Option<StreamMessage>) -> Vec<u64> {
+        match message.and_then(|message| message.kind) {
+            Some(Kind::Batch(batch)) => batch.events.iter().map(|event| event.sequence).collect(),
+            other => panic!("expected a batch, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_flushes_when_full() {
+        let broadcaster = broadcaster(16, 100);
+        let mut subscription = batched(&broadcaster, 3, Duration::from_secs(60), StreamStart::Live);
+        publish_markers(&broadcaster, 5);
+
+        assert_eq!(batch_sequences(subscription.next().await), [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn batch_flushes_at_deadline() {
+        let broadcaster = broadcaster(16, 100);
+        let mut subscription = batched(
+            &broadcaster,
+            100,
+            Duration::from_millis(20),
+            StreamStart::Live,
+        );
+        publish_markers(&broadcaster, 2);
+
+        assert_eq!(batch_sequences(subscription.next().await), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn batch_flushes_before_held_message() {
+        let broadcaster = broadcaster(2, 100);
+        publish_markers(&broadcaster, 1);
+        // Event 1 is replayed into the batch, then the live lane has lagged
+        let mut subscription = batched(
+            &broadcaster,
+            100,
+            Duration::from_millis(20),
+            StreamStart::ResumeAfter(0),
+        );
+        publish_markers(&broadcaster, 5);
+
+        assert_eq!(batch_sequences(subscription.next().await), [1]);
+        match subscription.next().await.and_then(|message| message.kind) {
+            Some(Kind::EventsDropped(dropped)) => assert_eq!(dropped.count, 3),
+            other => panic!("expected EventsDropped, got {:?}", other),
+        }
+        assert_eq!(batch_sequences(subscription.next().await), [5, 6]);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::capture as proto;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::process::Command;
@@ -93,10 +94,81 @@ impl SystemInfo {
         })
     }
 
+    /// Runs `collect` on the blocking thread pool, since it shells out to several slow commands.
+    pub async fn collect_async() -> Result<Self, String> {
+        tokio::task::spawn_blocking(Self::collect)
+            .await
+            .map_err(|e| format!("System info collection task failed: {e}"))?
+    }
+
     pub fn to_formatted_string(&self) -> String {
         serde_json::to_string_pretty(self)
             .unwrap_or_else(|_| "Failed to serialize system info".to_string())
     }
+
+    pub fn to_proto(&self) -> proto::SystemInfo {
+        let core = &self.system_info;
+        proto::SystemInfo {
+            system_info: Some(proto::SystemInfoCore {
+                directx_version: core.directx_version.clone(),
+                os_version: core.os_version.clone(),
+                real_os: core.real_os.clone(),
+                memory_mb: core.memory_mb,
+                physical_model: core.physical_model.clone(),
+                machine_signature: core.machine_signature.clone(),
+                user: core.user.clone(),
+                monitor_start_time: core.monitor_start_time.clone(),
+            }),
+            pci_devices: self
+                .pci_devices
+                .iter()
+                .map(|d| proto::PciDevice {
+                    id: d.id.clone(),
+                    device_type: d.device_type.clone(),
+                    name: d.name.clone(),
+                })
+                .collect(),
+            drives: self
+                .drives
+                .iter()
+                .map(|d| proto::DriveInfo {
+                    serial: d.serial.clone(),
+                })
+                .collect(),
+            network_info: Some(proto::NetworkInfo {
+                local_ip: self.network_info.local_ip.clone(),
+                public_ip: self.network_info.public_ip.clone(),
+            }),
+            video_cards: self
+                .video_cards
+                .iter()
+                .map(|c| proto::VideoCard {
+                    name: c.name.clone(),
+                    driver_version: c.driver_version.clone(),
+                })
+                .collect(),
+            monitors: self
+                .monitors
+                .iter()
+                .map(|m| proto::Monitor {
+                    model: m.model.clone(),
+                })
+                .collect(),
+            usb_input_devices: self
+                .usb_input_devices
+                .iter()
+                .map(|d| proto::UsbDevice {
+                    name: d.name.clone(),
+                    vendor_id: d.vendor_id.clone(),
+                    product_id: d.product_id.clone(),
+                })
+                .collect(),
+            processor_info: Some(proto::ProcessorInfo {
+                cpu_model: self.processor_info.cpu_model.clone(),
+                cpu_cores: self.processor_info.cpu_cores,
+            }),
+        }
+    }
 }
 
 /// Helper: Parse WMIC output into a vector of hashmaps (one hashmap per device/block).
@@ -408,7 +480,7 @@ fn get_video_cards() -> Result<Vec<VideoCard>, String> {
 fn get_monitors() -> Result<Vec<Monitor>, String> {
     // First try to get monitor info from WmiMonitorID which contains EDID data
     let output = Command::new("powershell")
-        .args(&[
+        .args([
             "-Command",
             "Get-WmiObject -Namespace root/wmi -Class WmiMonitorID | Select-Object -ExpandProperty UserFriendlyName",
         ])
@@ -419,9 +491,13 @@ fn get_monitors() -> Result<Vec<Monitor>, String> {
     let mut monitors = Vec::new();
 
     // Parse the output - each byte is on a separate line
-    let lines: Vec<&str> = output_str.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).collect();
+    let lines: Vec<&str> = output_str
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect();
     let mut current_bytes = Vec::new();
-    
+
     for line in lines {
         if let Ok(byte_val) = line.parse::<u8>() {
             current_bytes.push(byte_val);
@@ -429,19 +505,23 @@ fn get_monitors() -> Result<Vec<Monitor>, String> {
             if byte_val == 0 {
                 if let Some(model_name) = decode_byte_array(&current_bytes) {
                     if !model_name.is_empty() && model_name != "Generic PnP Monitor" {
-                        monitors.push(Monitor { model: Some(model_name) });
+                        monitors.push(Monitor {
+                            model: Some(model_name),
+                        });
                     }
                 }
                 current_bytes.clear();
             }
         }
     }
-    
+
     // Handle case where there's no null terminator at the end
     if !current_bytes.is_empty() {
         if let Some(model_name) = decode_byte_array(&current_bytes) {
             if !model_name.is_empty() && model_name != "Generic PnP Monitor" {
-                monitors.push(Monitor { model: Some(model_name) });
+                monitors.push(Monitor {
+                    model: Some(model_name),
+                });
             }
         }
     }
@@ -449,12 +529,7 @@ fn get_monitors() -> Result<Vec<Monitor>, String> {
     // Fallback to desktopmonitor if WmiMonitorID didn't work
     if monitors.is_empty() {
         let fallback_output = Command::new("wmic")
-            .args(&[
-                "desktopmonitor",
-                "get",
-                "Caption",
-                "/format:list",
-            ])
+            .args(["desktopmonitor", "get", "Caption", "/format:list"])
             .output()
             .map_err(|e| format!("WMIC desktopmonitor error: {e}"))?;
 
@@ -484,7 +559,7 @@ fn decode_byte_array(bytes: &[u8]) -> Option<String> {
     if bytes.is_empty() {
         return None;
     }
-    
+
     // Convert bytes to string, stopping at null terminator
     let mut result = String::new();
     for &byte in bytes {
@@ -495,7 +570,7 @@ fn decode_byte_array(bytes: &[u8]) -> Option<String> {
             result.push(byte as char);
         }
     }
-    
+
     if result.is_empty() {
         None
     } else {
@@ -560,11 +635,10 @@ fn get_processor_info() -> Result<ProcessorInfo, String> {
     let output_str = str::from_utf8(&output.stdout).map_err(|e| e.to_string())?;
     let blocks = parse_wmic_output(output_str);
 
-    let mut cpu_name = String::new();
-    for block in blocks {
-        cpu_name = block.get("Name").cloned().unwrap_or_default();
-        break;
-    }
+    let cpu_name = blocks
+        .first()
+        .and_then(|block| block.get("Name").cloned())
+        .unwrap_or_default();
 
     let core_output = Command::new("wmic")
         .args(["cpu", "get", "NumberOfLogicalProcessors", "/format:list"])
@@ -0,0 +1,38 @@
+//! Fixtures shared by unit tests.
+
+use std::time::Duration;
+
+use crate::broadcaster::{EventBroadcaster, ReplayLimits};
+use crate::capture::event::Payload;
+use crate::capture::{Marker, MouseMoveEvent};
+
+/// A broadcaster with `capacity` on both lanes, replaying up to
+/// `max_events` for a minute.
+pub fn broadcaster(capacity: usize, max_events: usize) -> EventBroadcaster {
+    EventBroadcaster::new(
+        capacity,
+        capacity,
+        ReplayLimits {
+            max_events,
+            max_age: Duration::from_secs(60),
+        },
+    )
+}
+
+pub fn mouse_move() -> Payload {
+    Payload::MouseMove(MouseMoveEvent { x: 1.0, y: 2.0 })
+}
+
+pub fn marker() -> Payload {
+    Payload::Marker(Marker {
+        name: "marker".to_string(),
+        ..Default::default()
+    })
+}
+
+/// Publishes `count` markers.
+pub fn publish_markers(broadcaster: &EventBroadcaster, count: usize) {
+    for _ in 0..count {
+        broadcaster.publish(marker());
+    }
+}
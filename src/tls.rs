@@ -0,0 +1,336 @@
+//! TLS for the gRPC listener. The certificate is either loaded from
+//! configured paths or self-signed by the agent on first run and kept for
+//! later launches, so clients can pin its fingerprint. Clients may in turn be
+//! required to present a certificate signed by a configured CA or matching a
+//! pinned fingerprint.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::crypto::{self, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{
+    DigitallySignedStruct, DistinguishedName, RootCertStore, ServerConfig, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use tokio_rustls::TlsAcceptor;
+
+use crate::options::TlsOptions;
+use crate::private_file::write_private;
+
+const CERT_FILE: &str = "agent-cert.pem";
+const KEY_FILE: &str = "agent-key.pem";
+/// Written next to a self-signed certificate for clients that pin it.
+const FINGERPRINT_FILE: &str = "agent-cert.sha256";
+
+/// Names the self-signed certificate is valid for.
+const SUBJECT_ALT_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+/// Server TLS settings and the fingerprint of the certificate presented.
+pub struct ServerTls {
+    pub acceptor: TlsAcceptor,
+    pub cert_path: PathBuf,
+    /// SHA-256 of the DER certificate as colon separated hex pairs
+    pub fingerprint: String,
+    /// Whether clients must present a certificate
+    pub client_auth: bool,
+    pinned_clients: Vec<String>,
+}
+
+/// A client certificate accepted during the handshake.
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    /// Subject distinguished name, e.g. "CN=moss-app"
+    pub subject: String,
+    pub fingerprint: String,
+    /// Whether the certificate was accepted by fingerprint rather than by CA
+    pub pinned: bool,
+}
+
+impl fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = if self.pinned { "pinned" } else { "CA" };
+        write!(f, "{} [{}]", self.subject, source)
+    }
+}
+
+/// Loads or creates the server certificate. Returns `None` when TLS is off.
+/// `accept_http1` also offers HTTP/1.1 during ALPN, for gRPC-Web.
+pub fn server_tls(options: &TlsOptions, accept_http1: bool) -> Result<Option<ServerTls>, String> {
+    let (cert_path, key_path) = match (&options.tls_cert, &options.tls_key) {
+        (Some(cert), Some(key)) => (cert.clone(), key.clone()),
+        _ if options.tls => {
+            let dir = options.tls_dir.clone().unwrap_or_else(default_dir);
+            ensure_self_signed(&dir)?
+        }
+        _ => return Ok(None),
+    };
+
+    let chain = read_certs(&cert_path)?;
+    let fingerprint = fingerprint(&chain[0]);
+    let key = fs::read(&key_path)
+        .map_err(|e| format!("Failed to read {}: {}", key_path.display(), e))
+        .and_then(|pem| match rustls_pemfile::private_key(&mut &pem[..]) {
+            Ok(Some(key)) => Ok(key),
+            Ok(None) => Err(format!("No private key found in {}", key_path.display())),
+            Err(e) => Err(format!("Invalid private key {}: {}", key_path.display(), e)),
+        })?;
+
+    let pinned_clients = options.tls_client_fingerprints.clone();
+    let verifier = client_verifier(options.tls_client_ca.as_deref(), pinned_clients.clone())?;
+    let client_auth = verifier.offer_client_auth();
+    let mut config = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(chain, key)
+        .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    if accept_http1 {
+        config.alpn_protocols.push(b"http/1.1".to_vec());
+    }
+
+    Ok(Some(ServerTls {
+        acceptor: TlsAcceptor::from(Arc::new(config)),
+        cert_path,
+        fingerprint,
+        client_auth,
+        pinned_clients,
+    }))
+}
+
+impl ServerTls {
+    /// Identifies the client of an established connection.
+    pub fn client_identity(&self, certs: Option<&[CertificateDer<'_>]>) -> Option<ClientIdentity> {
+        let cert = certs?.first()?;
+        let fingerprint = fingerprint(cert);
+        let subject = x509_parser::parse_x509_certificate(cert)
+            .map(|(_, cert)| cert.subject().to_string())
+            .unwrap_or_default();
+        Some(ClientIdentity {
+            subject,
+            pinned: self.pinned_clients.contains(&fingerprint),
+            fingerprint,
+        })
+    }
+}
+
+fn client_verifier(
+    ca_path: Option<&Path>,
+    pinned: Vec<String>,
+) -> Result<Arc<dyn ClientCertVerifier>, String> {
+    let ca = match ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("Invalid CA certificate {}: {}", path.display(), e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|e| format!("Invalid client CA {}: {}", path.display(), e))?;
+            Some(verifier)
+        }
+        None => None,
+    };
+    if ca.is_none() && pinned.is_empty() {
+        return Ok(WebPkiClientVerifier::no_client_auth());
+    }
+
+    Ok(Arc::new(ClientVerifier {
+        ca,
+        pinned,
+        algorithms: crypto::ring::default_provider().signature_verification_algorithms,
+    }))
+}
+
+/// Accepts client certificates whose fingerprint is pinned, and otherwise
+/// those the CA verifier accepts.
+#[derive(Debug)]
+struct ClientVerifier {
+    ca: Option<Arc<dyn ClientCertVerifier>>,
+    pinned: Vec<String>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ClientCertVerifier for ClientVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        // Pinned certificates are usually self-signed, so hint no issuer to
+        // keep clients from filtering them out
+        match &self.ca {
+            Some(ca) if self.pinned.is_empty() => ca.root_hint_subjects(),
+            _ => &[],
+        }
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        if self.pinned.contains(&fingerprint(end_entity)) {
+            return Ok(ClientCertVerified::assertion());
+        }
+        match &self.ca {
+            Some(ca) => ca.verify_client_cert(end_entity, intermediates, now),
+            None => Err(rustls::Error::General(
+                "Client certificate is not pinned".to_string(),
+            )),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Per-user directory for the self-signed certificate.
+fn default_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("moss-agent")
+        .join("tls")
+}
+
+/// Returns the certificate and key paths in `dir`, generating a self-signed
+/// pair first if there is none yet.
+fn ensure_self_signed(dir: &Path) -> Result<(PathBuf, PathBuf), String> {
+    let cert_path = dir.join(CERT_FILE);
+    let key_path = dir.join(KEY_FILE);
+    if cert_path.exists() && key_path.exists() {
+        return Ok((cert_path, key_path));
+    }
+
+    let names = SUBJECT_ALT_NAMES
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    let certificate = rcgen::generate_simple_self_signed(names)
+        .map_err(|e| format!("Failed to generate certificate: {}", e))?;
+    // Serialize once: every call signs again and yields a different certificate
+    let cert_pem = certificate
+        .serialize_pem()
+        .map_err(|e| format!("Failed to encode certificate: {}", e))?;
+
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    write_private(
+        &key_path,
+        certificate.serialize_private_key_pem().as_bytes(),
+    )?;
+    fs::write(&cert_path, &cert_pem)
+        .map_err(|e| format!("Failed to write {}: {}", cert_path.display(), e))?;
+    let fingerprint_path = dir.join(FINGERPRINT_FILE);
+    let fingerprint = fingerprint(&read_certs(&cert_path)?[0]);
+    fs::write(&fingerprint_path, format!("{}\n", fingerprint))
+        .map_err(|e| format!("Failed to write {}: {}", fingerprint_path.display(), e))?;
+
+    println!(
+        "[INFO] Generated self-signed TLS certificate {}",
+        cert_path.display()
+    );
+    Ok((cert_path, key_path))
+}
+
+/// Reads all certificates of a PEM file.
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let pem = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let certs = rustls_pemfile::certs(&mut &pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// SHA-256 of a certificate as colon separated upper-case hex pairs, as
+/// printed by `openssl x509 -fingerprint -sha256`.
+pub fn fingerprint(cert: &CertificateDer<'_>) -> String {
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Parses a pinned SHA-256 fingerprint in hex, with or without colons, into
+/// the form `fingerprint` produces.
+pub fn parse_fingerprint(value: &str) -> Result<String, String> {
+    let hex: String = value
+        .trim()
+        .trim_start_matches("sha256:")
+        .chars()
+        .filter(|c| *c != ':')
+        .collect();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("expected a SHA-256 fingerprint, got {:?}", value));
+    }
+    Ok(hex
+        .to_uppercase()
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| String::from_utf8_lossy(pair).into_owned())
+        .collect::<Vec<_>>()
+        .join(":"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLONS: &str = "3A:7F:00:11:22:33:44:55:66:77:88:99:AA:BB:CC:DD:\
+                          EE:FF:00:11:22:33:44:55:66:77:88:99:AA:BB:CC:C2";
+
+    #[test]
+    fn parses_fingerprint_with_or_without_colons() {
+        assert_eq!(parse_fingerprint(COLONS).unwrap(), COLONS);
+        let bare = COLONS.replace(':', "").to_lowercase();
+        assert_eq!(parse_fingerprint(&bare).unwrap(), COLONS);
+        let prefixed = format!(" sha256:{} ", bare);
+        assert_eq!(parse_fingerprint(&prefixed).unwrap(), COLONS);
+    }
+
+    #[test]
+    fn rejects_malformed_fingerprint() {
+        assert!(parse_fingerprint("3A:7F").is_err());
+        assert!(parse_fingerprint(&COLONS.replace("C2", "G2")).is_err());
+        assert!(parse_fingerprint(&format!("{}:00", COLONS)).is_err());
+    }
+
+    #[test]
+    fn parsed_fingerprint_matches_certificate() {
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .unwrap()
+            .serialize_der()
+            .unwrap();
+        let certificate = CertificateDer::from(certificate);
+        let hex: String = Sha256::digest(certificate.as_ref())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(parse_fingerprint(&hex).unwrap(), fingerprint(&certificate));
+    }
+}
@@ -0,0 +1,131 @@
+//! WebSocket bridge for local web pages such as in-game overlays.
+//!
+//! A socket behaves like a `Session` stream: the agent sends
+//! `SessionMessage`s carrying events and command acks, and the page may send
+//! `SessionCommand`s. Frames are JSON text or binary protobuf, chosen by
+//! subprotocol; JSON is used when the client asks for neither.
+//!
+//! Upgrades pass the gateway's origin and token checks like any other
+//! request, and commands are limited to the scope of the token presented.
+
+use std::net::SocketAddr;
+
+use axum::extract::rejection::QueryRejection;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, Extension, Query, State};
+use axum::http::HeaderMap;
+use axum::response::Response;
+use prost::Message as _;
+use tonic::{Code, Status};
+
+use crate::auth::Scope;
+use crate::capture::session_message::Kind as SessionKind;
+use crate::capture::{CommandAck, SessionCommand, SessionMessage};
+use crate::gateway::{ApiError, EventsQuery};
+use crate::subscription::Subscription;
+use crate::MyCaptureService;
+
+const JSON_PROTOCOL: &str = "moss.v1.json";
+const PROTOBUF_PROTOCOL: &str = "moss.v1.proto";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Json,
+    Protobuf,
+}
+
+/// Accepts a WebSocket connection, subscribing before the upgrade so
+/// invalid filters are reported as a plain HTTP error.
+pub async fn upgrade(
+    ws: WebSocketUpgrade,
+    State(service): State<MyCaptureService>,
+    Extension(scope): Extension<Scope>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    query: Result<Query<EventsQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let Query(query) = query?;
+    let request = query
+        .stream_request(None)
+        .map_err(Status::invalid_argument)?;
+    let subscription = service
+        .subscribe(request, peer.to_string())
+        .map_err(|(code, e)| Status::new(code, e))?;
+
+    // Pick the first subprotocol offered by the client that we speak
+    let protocol = headers
+        .get("sec-websocket-protocol")
+        .and_then(|value| value.to_str().ok())
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .find(|protocol| [JSON_PROTOCOL, PROTOBUF_PROTOCOL].contains(protocol));
+    let (ws, encoding) = match protocol {
+        Some(PROTOBUF_PROTOCOL) => (ws.protocols([PROTOBUF_PROTOCOL]), Encoding::Protobuf),
+        Some(_) => (ws.protocols([JSON_PROTOCOL]), Encoding::Json),
+        None => (ws, Encoding::Json),
+    };
+
+    Ok(ws.on_upgrade(move |socket| run(socket, service, subscription, scope, encoding)))
+}
+
+async fn run(
+    mut socket: WebSocket,
+    service: MyCaptureService,
+    mut subscription: Subscription,
+    scope: Scope,
+    encoding: Encoding,
+) {
+    loop {
+        let reply = tokio::select! {
+            message = subscription.next() => match message {
+                Some(message) => SessionMessage {
+                    kind: Some(SessionKind::Stream(message)),
+                },
+                None => break,
+            },
+            frame = socket.recv() => {
+                let command = match frame {
+                    Some(Ok(Message::Text(text))) if encoding == Encoding::Json => {
+                        serde_json::from_str::<SessionCommand>(&text).map_err(|e| e.to_string())
+                    }
+                    Some(Ok(Message::Binary(bytes))) if encoding == Encoding::Protobuf => {
+                        SessionCommand::decode(bytes.as_slice()).map_err(|e| e.to_string())
+                    }
+                    Some(Ok(Message::Text(_) | Message::Binary(_))) => {
+                        Err("Frame type does not match the negotiated subprotocol".to_string())
+                    }
+                    // Pings are answered by axum
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                };
+                let ack = match command {
+                    Ok(command) => service.run_command(command, &mut subscription, scope).await,
+                    Err(error) => CommandAck {
+                        code: Code::InvalidArgument as i32,
+                        error: format!("Invalid command: {}", error),
+                        ..Default::default()
+                    },
+                };
+                SessionMessage {
+                    kind: Some(SessionKind::Ack(ack)),
+                }
+            }
+        };
+
+        let frame = match encoding {
+            Encoding::Json => match serde_json::to_string(&reply) {
+                Ok(text) => Message::Text(text),
+                Err(e) => {
+                    eprintln!("[ERROR] Failed to encode WebSocket message: {}", e);
+                    continue;
+                }
+            },
+            Encoding::Protobuf => Message::Binary(reply.encode_to_vec()),
+        };
+        if socket.send(frame).await.is_err() {
+            break;
+        }
+    }
+    println!("[WARN] WebSocket stream ended");
+}